@@ -133,3 +133,5 @@ function K_IS(i) {
   return k_is[i];
 }
 function NUM_PUBLIC_INPUTS() { return 8; }
+function PUBLIC_INPUTS_PER_SIGNAL() { return 1; }
+function NUM_PUBLIC_SIGNALS() { return (NUM_PUBLIC_INPUTS() + PUBLIC_INPUTS_PER_SIGNAL() - 1) \ PUBLIC_INPUTS_PER_SIGNAL(); }
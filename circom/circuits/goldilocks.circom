@@ -18,6 +18,16 @@ template LessNBits(N) {
   x === tmp2[N];
 }
 
+// Verifies x < Order(), i.e. x is a canonical Goldilocks element
+template GlCanonical() {
+  signal input x;
+  component c0 = LessNBits(64);
+  c0.x <== x;
+  // With x < 2^64, x + 2^64 - Order() < 2^64 iff x < Order()
+  component c1 = LessNBits(64);
+  c1.x <== x + 2 ** 64 - Order();
+}
+
 // Gl: Goldilocks
 // range check d < 1 << N
 template GlReduce(N) {
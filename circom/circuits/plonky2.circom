@@ -34,6 +34,24 @@ template VerifyPlonky2Proof() {
   signal input fri_final_poly_ext_v[NUM_FRI_FINAL_POLY_EXT_V()][2];
   signal input fri_pow_witness;
   signal input public_inputs[NUM_PUBLIC_INPUTS()];
+  signal output public_signals[NUM_PUBLIC_SIGNALS()];
+
+  // Packs PUBLIC_INPUTS_PER_SIGNAL() inputs into each public signal, the first one in the low
+  // 64 bits. Every input must be canonical, as the Solidity wrapper requires, so that each
+  // plonky2 public input has a single encoding and the packing is unique.
+  var packed[NUM_PUBLIC_SIGNALS()];
+  for (var i = 0; i < NUM_PUBLIC_SIGNALS(); i++) {
+    packed[i] = 0;
+  }
+  component public_input_range[NUM_PUBLIC_INPUTS()];
+  for (var i = 0; i < NUM_PUBLIC_INPUTS(); i++) {
+    public_input_range[i] = GlCanonical();
+    public_input_range[i].x <== public_inputs[i];
+    packed[i \ PUBLIC_INPUTS_PER_SIGNAL()] += public_inputs[i] * 2 ** (64 * (i % PUBLIC_INPUTS_PER_SIGNAL()));
+  }
+  for (var i = 0; i < NUM_PUBLIC_SIGNALS(); i++) {
+    public_signals[i] <== packed[i];
+  }
 
   component public_input_hasher = HashNoPad_GL(NUM_PUBLIC_INPUTS(), 4);
   public_input_hasher.in <== public_inputs;
@@ -120,4 +138,4 @@ template VerifyPlonky2Proof() {
   verify_fri_proof.fri_query_indices <== get_challenges.fri_query_indices;
 }
 
-component main {public [public_inputs]} = VerifyPlonky2Proof();
+component main = VerifyPlonky2Proof();
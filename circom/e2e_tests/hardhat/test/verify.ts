@@ -2,19 +2,23 @@ import {ethers} from "hardhat";
 import "@nomiclabs/hardhat-etherscan";
 import {assert, expect} from "chai";
 
+function readCalldata() {
+    const fs = require("fs");
+    let text = fs.readFileSync("./test/public.txt").toString();
+    text = text.replace(/\s+/g, '');
+    text = text.replace(/\[+/g, '');
+    text = text.replace(/]+/g, '');
+    text = text.replace(/"+/g, '');
+    return text.split(",");
+}
+
 describe("Groth16", function () {
     it("Should return true when proof is correct", async function () {
         const verifierFactory = await ethers.getContractFactory("Verifier");
         const verifier = await verifierFactory.deploy();
         await verifier.deployed();
 
-        const fs = require("fs");
-        let text = fs.readFileSync("./test/public.txt").toString();
-        text = text.replace(/\s+/g, '');
-        text = text.replace(/\[+/g, '');
-        text = text.replace(/]+/g, '');
-        text = text.replace(/"+/g, '');
-        const p = text.split(",");
+        const p = readCalldata();
         let public_inputs = [];
         for (let i = 0; i < p.length - 8; i++) {
             public_inputs.push(p[8 + i]);
@@ -25,4 +29,50 @@ describe("Groth16", function () {
             [p[6], p[7]], public_inputs
         )).to.equal(true);
     });
+
+    it("Should accept the plonky2 public inputs through the wrapper", async function () {
+        const verifierFactory = await ethers.getContractFactory("Verifier");
+        const verifier = await verifierFactory.deploy();
+        await verifier.deployed();
+        const wrapperFactory = await ethers.getContractFactory("Plonky2Verifier");
+        const wrapper = await wrapperFactory.deploy(verifier.address);
+        await wrapper.deployed();
+
+        const p = readCalldata();
+        const fs = require("fs");
+        const public_inputs = JSON.parse(fs.readFileSync("../../test/data/proof.json").toString()).public_inputs;
+        expect(await wrapper.verifyProof(
+            public_inputs,
+            [p[0], p[1]],
+            [[p[2], p[3]], [p[4], p[5]]],
+            [p[6], p[7]]
+        )).to.equal(true);
+    });
+
+    it("Should reject a non-canonical public input in the wrapper", async function () {
+        const verifierFactory = await ethers.getContractFactory("Verifier");
+        const verifier = await verifierFactory.deploy();
+        await verifier.deployed();
+        const wrapperFactory = await ethers.getContractFactory("Plonky2Verifier");
+        const wrapper = await wrapperFactory.deploy(verifier.address);
+        await wrapper.deployed();
+
+        const p = readCalldata();
+        const fs = require("fs");
+        const public_inputs = JSON.parse(fs.readFileSync("../../test/data/proof.json").toString()).public_inputs;
+        // The Goldilocks order, which the circuit rejects as well.
+        public_inputs[0] = "18446744069414584321";
+        let reverted = false;
+        try {
+            await wrapper.verifyProof(
+                public_inputs,
+                [p[0], p[1]],
+                [[p[2], p[3]], [p[4], p[5]]],
+                [p[6], p[7]]
+            );
+        } catch (e) {
+            reverted = true;
+        }
+        assert(reverted, "accepted a non-canonical public input");
+    });
 });
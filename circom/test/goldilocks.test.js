@@ -1,4 +1,5 @@
 const path = require("path");
+const {assert} = require("chai");
 
 const wasm_tester = require("circom_tester").wasm;
 
@@ -21,3 +22,31 @@ describe("Goldilocks Circuit Test", function () {
         await circuit.assertOut(w, {out: 1});
     });
 });
+
+describe("Goldilocks Canonical Test", function () {
+    let circuit;
+
+    this.timeout(10000000);
+
+    before(async () => {
+        circuit = await wasm_tester(path.join(__dirname, "circuits", "gl_canonical.test.circom"), {});
+    });
+
+    it("Should accept the largest canonical element", async () => {
+        const w = await circuit.calculateWitness({x: "18446744069414584320"}, true);
+        await circuit.checkConstraints(w);
+    });
+
+    it("Should reject non-canonical limbs", async () => {
+        // The order itself, and the largest 64-bit limb.
+        for (const x of ["18446744069414584321", "18446744073709551615"]) {
+            let rejected = false;
+            try {
+                await circuit.calculateWitness({x: x}, true);
+            } catch (e) {
+                rejected = true;
+            }
+            assert(rejected, `accepted ${x}`);
+        }
+    });
+});
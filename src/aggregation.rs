@@ -0,0 +1,246 @@
+use anyhow::{ensure, Result};
+use log::{info, log_enabled, Level};
+use plonky2::field::extension::Extendable;
+use plonky2::field::types::Field;
+use plonky2::hash::hash_types::{HashOutTarget, MerkleCapTarget, RichField};
+use plonky2::hash::merkle_proofs::MerkleProofTarget;
+use plonky2::hash::merkle_tree::MerkleTree;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::Target;
+use plonky2::iop::witness::{PartialWitness, WitnessWrite};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::circuit_data::{
+    CircuitConfig, CommonCircuitData, VerifierCircuitTarget, VerifierOnlyCircuitData,
+};
+use plonky2::plonk::config::{AlgebraicHasher, GenericConfig, GenericHashOut, Hasher};
+use plonky2::plonk::prover::prove;
+use plonky2::timed;
+use plonky2::util::timing::TimingTree;
+
+use crate::types::{ProofTuple, VDProof};
+
+#[derive(Debug, Clone)]
+pub struct VDProofTarget {
+    pub merkle_proof_target: MerkleProofTarget,
+    pub index_target: Target,
+}
+
+/// The leaf committing to a verifier key: the circuit digest followed by the constants and
+/// sigmas cap. Both are needed, as the proof is checked against the cap while only the digest
+/// is observed by the challenger.
+pub fn verifier_data_leaf<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    const D: usize,
+>(
+    vd: &VerifierOnlyCircuitData<C, D>,
+) -> Vec<F> {
+    let mut leaf = vd.circuit_digest.to_vec();
+    for h in vd.constants_sigmas_cap.0.iter() {
+        leaf.extend(h.to_vec());
+    }
+    leaf
+}
+
+/// Builds the tree of allowlisted verifier keys. The leaves are padded with zeros up to the
+/// next power of two, so the index of a circuit is its position in `vds`.
+pub fn verifier_data_tree<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    const D: usize,
+>(
+    vds: &[VerifierOnlyCircuitData<C, D>],
+) -> Result<MerkleTree<F, PoseidonHash>> {
+    ensure!(!vds.is_empty(), "no verifier keys to allowlist");
+    let mut leaves: Vec<Vec<F>> = vds.iter().map(|vd| verifier_data_leaf(vd)).collect();
+    let leaf_len = leaves[0].len();
+    leaves.resize(leaves.len().next_power_of_two(), vec![F::ZERO; leaf_len]);
+    Ok(MerkleTree::new(leaves, 0))
+}
+
+pub fn make_vd_proof<F: RichField>(tree: &MerkleTree<F, PoseidonHash>, index: usize) -> VDProof<F> {
+    VDProof {
+        merkle_proof: tree.prove(index),
+        index: F::from_canonical_usize(index),
+        root: tree.cap.0[0],
+    }
+}
+
+/// Constrains the verifier key in `inner_data` to be a leaf of the tree with root `root_target`.
+pub fn verify_vd_membership<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    inner_data: &VerifierCircuitTarget,
+    root_target: HashOutTarget,
+    tree_height: usize,
+) -> VDProofTarget {
+    let merkle_proof_target = MerkleProofTarget {
+        siblings: builder.add_virtual_hashes(tree_height),
+    };
+    let index_target = builder.add_virtual_target();
+    let index_bits = builder.split_le(index_target, tree_height);
+
+    let mut leaf = inner_data.circuit_digest.elements.to_vec();
+    for h in inner_data.constants_sigmas_cap.0.iter() {
+        leaf.extend(h.elements);
+    }
+    builder.verify_merkle_proof_to_cap::<PoseidonHash>(
+        leaf,
+        &index_bits,
+        &MerkleCapTarget(vec![root_target]),
+        &merkle_proof_target,
+    );
+
+    VDProofTarget {
+        merkle_proof_target,
+        index_target,
+    }
+}
+
+pub fn set_vd_proof_target<F: RichField>(
+    pw: &mut PartialWitness<F>,
+    vd_proof_target: &VDProofTarget,
+    vd_proof: &VDProof<F>,
+) {
+    pw.set_target(vd_proof_target.index_target, vd_proof.index);
+    for (ht, h) in vd_proof_target
+        .merkle_proof_target
+        .siblings
+        .iter()
+        .zip(vd_proof.merkle_proof.siblings.iter())
+    {
+        pw.set_hash_target(*ht, *h);
+    }
+}
+
+/// Verifies a set of inner proofs in one circuit. The inner circuits may differ in degree, gates
+/// and FRI settings, as long as they share the `InnerC` config; each verifier key is checked
+/// against the allowlisted root carried by its `VDProof`. The public inputs are the root,
+/// followed by the public inputs of every inner proof.
+pub fn aggregate_proofs<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    InnerC: GenericConfig<D, F = F>,
+    const D: usize,
+>(
+    inner_proofs: Vec<(ProofTuple<F, InnerC, D>, VDProof<F>)>,
+    config: &CircuitConfig,
+) -> Result<ProofTuple<F, C, D>>
+where
+    InnerC::Hasher: AlgebraicHasher<F>,
+    [(); C::Hasher::HASH_SIZE]:,
+{
+    ensure!(!inner_proofs.is_empty(), "nothing to aggregate");
+    let root = inner_proofs[0].1.root;
+    let tree_height = inner_proofs[0].1.merkle_proof.siblings.len();
+    ensure!(
+        inner_proofs
+            .iter()
+            .all(|(_, vd_proof)| vd_proof.root == root
+                && vd_proof.merkle_proof.siblings.len() == tree_height),
+        "all verifier data proofs must be against the same tree"
+    );
+
+    let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+    let mut pw = PartialWitness::new();
+
+    let root_target = builder.add_virtual_hash();
+    pw.set_hash_target(root_target, root);
+    builder.register_public_inputs(&root_target.elements);
+
+    for ((inner_proof, inner_vd, inner_cd), vd_proof) in inner_proofs.iter() {
+        let pt = builder.add_virtual_proof_with_pis::<InnerC>(inner_cd);
+        pw.set_proof_with_pis_target(&pt, inner_proof);
+
+        let inner_data = VerifierCircuitTarget {
+            constants_sigmas_cap: builder.add_virtual_cap(inner_cd.config.fri_config.cap_height),
+            circuit_digest: builder.add_virtual_hash(),
+        };
+        pw.set_cap_target(
+            &inner_data.constants_sigmas_cap,
+            &inner_vd.constants_sigmas_cap,
+        );
+        pw.set_hash_target(inner_data.circuit_digest, inner_vd.circuit_digest);
+
+        let vd_proof_target =
+            verify_vd_membership(&mut builder, &inner_data, root_target, tree_height);
+        set_vd_proof_target(&mut pw, &vd_proof_target, vd_proof);
+
+        builder.register_public_inputs(&pt.public_inputs);
+        builder.verify_proof::<InnerC>(&pt, &inner_data, inner_cd);
+    }
+
+    // Gate counts and the timing tree are only printed when debug logging is enabled.
+    if log_enabled!(Level::Debug) {
+        builder.print_gate_counts(0);
+    }
+
+    let mut timing = TimingTree::new("aggregation proof", Level::Debug);
+    let data = timed!(timing, Level::Debug, "build circuit", builder.build::<C>());
+    info!(
+        "aggregation circuit of {} proofs has 2^{} gates",
+        inner_proofs.len(),
+        data.common.degree_bits()
+    );
+    let proof = timed!(
+        timing,
+        Level::Debug,
+        "prove",
+        prove(&data.prover_only, &data.common, pw, &mut timing)?
+    );
+    timed!(timing, Level::Debug, "verify", data.verify(proof.clone())?);
+    if log_enabled!(Level::Debug) {
+        timing.print();
+    }
+
+    Ok((proof, data.verifier_only, data.common))
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::plonk::circuit_data::CircuitConfig;
+
+    use crate::deposit::make_deposit_proof;
+    use crate::sequencer::generate_transactions;
+    use crate::test_utils::{dummy_proof, high_rate_config};
+    use crate::types::{TransactionKind, C, D, F};
+
+    use super::*;
+
+    #[test]
+    fn test_aggregate_heterogeneous_proofs() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+
+        let deposit_tx = generate_transactions::<F, D>(vec![TransactionKind::Deposit]).unwrap();
+        let deposit =
+            make_deposit_proof::<F, C, D>(&standard_config, deposit_tx[0].clone()).unwrap();
+        let other = dummy_proof::<F, C, D>(&high_rate_config(), 1 << 13, 0).unwrap();
+
+        let tree =
+            verifier_data_tree::<F, C, D>(&[deposit.1.clone(), other.1.clone()]).unwrap();
+        let inner_proofs = vec![
+            (deposit, make_vd_proof(&tree, 0)),
+            (other, make_vd_proof(&tree, 1)),
+        ];
+        let (proof, _, _) =
+            aggregate_proofs::<F, C, C, D>(inner_proofs, &standard_config).unwrap();
+        assert_eq!(proof.public_inputs[0..4], tree.cap.0[0].elements);
+    }
+
+    #[test]
+    fn test_verifier_data_tree_rejects_empty_input() {
+        assert!(verifier_data_tree::<F, C, D>(&[]).is_err());
+    }
+
+    #[test]
+    // The Merkle proof computes a different root than the allowlisted one.
+    #[should_panic(expected = "set twice with different values")]
+    fn test_aggregate_rejects_unregistered_circuit() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let registered = dummy_proof::<F, C, D>(&standard_config, 1 << 12, 0).unwrap();
+        let unregistered = dummy_proof::<F, C, D>(&standard_config, 1 << 13, 0).unwrap();
+
+        let tree = verifier_data_tree::<F, C, D>(&[registered.1.clone()]).unwrap();
+        let vd_proof = make_vd_proof(&tree, 0);
+        aggregate_proofs::<F, C, C, D>(vec![(unregistered, vd_proof)], &standard_config).unwrap();
+    }
+}
@@ -0,0 +1,443 @@
+use anyhow::ensure;
+use anyhow::Ok;
+use anyhow::Result;
+use plonky2::field::extension::Extendable;
+use plonky2::field::types::Field;
+use plonky2::hash::hash_types::HashOut;
+use plonky2::hash::hash_types::HashOutTarget;
+use plonky2::hash::hash_types::MerkleCapTarget;
+use plonky2::hash::hash_types::RichField;
+use plonky2::hash::merkle_proofs::MerkleProofTarget;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::BoolTarget;
+use plonky2::iop::target::Target;
+use plonky2::iop::witness::PartialWitness;
+use plonky2::iop::witness::WitnessWrite;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::circuit_data::CircuitConfig;
+use plonky2::plonk::config::GenericConfig;
+use plonky2::plonk::config::Hasher;
+use crate::types::{withdraw_signature, transfer_signature};
+use crate::types::BALANCE_BITS;
+use crate::types::ProofTuple;
+use crate::types::Transaction;
+use crate::types::TransactionKind;
+use crate::types::TransferReceiver;
+
+
+/// One transaction of a batch. Every slot can apply a deposit, a withdrawal or a transfer: the
+/// first position is credited or debited, taking the tree to `root_intermediate_target`, and the
+/// second position is credited with the transferred amount, which is zero unless the slot is a
+/// transfer.
+#[derive(Debug, Clone)]
+pub struct BatchTransactionTargets {
+    pub is_deposit_target: BoolTarget,
+    pub is_withdraw_target: BoolTarget,
+    pub is_transfer_target: BoolTarget,
+    pub root_intermediate_target: HashOutTarget,
+    pub amount_target: Target,
+    // first position
+    pub merkle_proof_target: MerkleProofTarget,
+    pub private_key_target: Vec<Target>,
+    pub pubkey_target: HashOutTarget,
+    pub balance_before_target: Target,
+    pub nonce_target: Target,
+    pub position_index_target: Target,
+    // second position
+    pub to_merkle_proof_target: MerkleProofTarget,
+    pub to_pubkey_target: HashOutTarget,
+    pub to_balance_before_target: Target,
+    pub to_nonce_target: Target,
+    pub to_position_index_target: Target,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchTargets {
+    pub root_before_target: HashOutTarget,
+    pub root_after_target: HashOutTarget,
+    pub transactions_hash_target: HashOutTarget,
+    pub transactions: Vec<BatchTransactionTargets>,
+}
+
+fn leaf(pubkey: HashOutTarget, balance: Target, nonce: Target) -> Vec<Target> {
+    [pubkey.elements.to_vec(), vec![balance, nonce]].concat()
+}
+
+fn select_hash<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    b: BoolTarget,
+    x: HashOutTarget,
+    y: HashOutTarget,
+) -> HashOutTarget {
+    HashOutTarget {
+        elements: [0, 1, 2, 3].map(|i| builder.select(b, x.elements[i], y.elements[i])),
+    }
+}
+
+/// Applies `n` transactions in sequence. The public inputs are the first root, the last root
+/// and the hash of the transaction list, see `batch_transactions_hash`.
+pub fn batch_circuit<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> , const D: usize>(n: usize, tree_height: usize, builder: &mut CircuitBuilder<F, D>) -> BatchTargets {
+    let root_before_target = builder.add_virtual_hash();
+    let zero = builder.zero();
+    let zero_hash = HashOutTarget { elements: [zero; 4] };
+
+    let mut root_target = root_before_target;
+    let mut transactions_hash_target = zero_hash;
+    let mut transactions = Vec::with_capacity(n);
+    for _ in 0..n {
+        let is_deposit_target = builder.add_virtual_bool_target_safe();
+        let is_withdraw_target = builder.add_virtual_bool_target_safe();
+        let is_transfer_target = builder.add_virtual_bool_target_safe();
+        let num_types = builder.add_many(&[is_deposit_target.target, is_withdraw_target.target, is_transfer_target.target]);
+        builder.assert_one(num_types);
+        let withdraw_code = builder.mul_const(F::from_canonical_usize(TransactionKind::Withdraw as usize), is_withdraw_target.target);
+        let transfer_code = builder.mul_const(F::from_canonical_usize(TransactionKind::Transfer as usize), is_transfer_target.target);
+        let deposit_code = builder.mul_const(F::from_canonical_usize(TransactionKind::Deposit as usize), is_deposit_target.target);
+        let transaction_type_target = builder.add_many(&[deposit_code, withdraw_code, transfer_code]);
+
+        let amount_target = builder.add_virtual_target();
+        builder.range_check(amount_target, BALANCE_BITS);
+
+        // First position. Withdrawals and transfers must come from the owner.
+        let merkle_proof_target = MerkleProofTarget {
+            siblings: builder.add_virtual_hashes(tree_height),
+        };
+        let private_key_target = builder.add_virtual_targets(4);
+        let owner_pubkey_target = builder.hash_n_to_hash_no_pad::<PoseidonHash>(private_key_target.clone());
+        let pubkey_target = builder.add_virtual_hash();
+        let checked_pubkey_target = select_hash(builder, is_deposit_target, pubkey_target, owner_pubkey_target);
+        let balance_before_target = builder.add_virtual_target();
+        let nonce_target = builder.add_virtual_target();
+        let position_index_target = builder.add_virtual_target();
+        let position_index_bits = builder.split_le(position_index_target, tree_height);
+
+        builder.verify_merkle_proof_to_cap::<PoseidonHash>(
+            leaf(checked_pubkey_target, balance_before_target, nonce_target),
+            &position_index_bits,
+            &MerkleCapTarget(vec![root_target]),
+            &merkle_proof_target,
+        );
+
+        builder.range_check(balance_before_target, BALANCE_BITS);
+        let credited_target = builder.add(balance_before_target, amount_target);
+        let debited_target = builder.sub(balance_before_target, amount_target);
+        let balance_after_target = builder.select(is_deposit_target, credited_target, debited_target);
+        builder.range_check(balance_after_target, BALANCE_BITS);
+        let is_not_deposit_target = builder.not(is_deposit_target);
+        let nonce_after_target = builder.add(nonce_target, is_not_deposit_target.target);
+
+        let root_intermediate_target = builder.add_virtual_hash();
+        builder.verify_merkle_proof_to_cap::<PoseidonHash>(
+            leaf(checked_pubkey_target, balance_after_target, nonce_after_target),
+            &position_index_bits,
+            &MerkleCapTarget(vec![root_intermediate_target]),
+            &merkle_proof_target,
+        );
+
+        // Second position, credited with the amount of a transfer and with zero otherwise.
+        let to_merkle_proof_target = MerkleProofTarget {
+            siblings: builder.add_virtual_hashes(tree_height),
+        };
+        let to_pubkey_target = builder.add_virtual_hash();
+        let to_balance_before_target = builder.add_virtual_target();
+        let to_nonce_target = builder.add_virtual_target();
+        let to_position_index_target = builder.add_virtual_target();
+        let to_position_index_bits = builder.split_le(to_position_index_target, tree_height);
+        // Unless the slot is a transfer, the second position is the first one, so the receiving
+        // position hashed into the commitment cannot be picked freely.
+        let is_not_transfer_target = builder.not(is_transfer_target);
+        let index_difference_target = builder.sub(to_position_index_target, position_index_target);
+        let masked_difference_target = builder.mul(index_difference_target, is_not_transfer_target.target);
+        builder.assert_zero(masked_difference_target);
+
+        builder.verify_merkle_proof_to_cap::<PoseidonHash>(
+            leaf(to_pubkey_target, to_balance_before_target, to_nonce_target),
+            &to_position_index_bits,
+            &MerkleCapTarget(vec![root_intermediate_target]),
+            &to_merkle_proof_target,
+        );
+
+        builder.range_check(to_balance_before_target, BALANCE_BITS);
+        let credit_target = builder.mul(amount_target, is_transfer_target.target);
+        let to_balance_after_target = builder.add(to_balance_before_target, credit_target);
+        builder.range_check(to_balance_after_target, BALANCE_BITS);
+
+        let root_after_target = builder.add_virtual_hash();
+        builder.verify_merkle_proof_to_cap::<PoseidonHash>(
+            leaf(to_pubkey_target, to_balance_after_target, to_nonce_target),
+            &to_position_index_bits,
+            &MerkleCapTarget(vec![root_after_target]),
+            &to_merkle_proof_target,
+        );
+
+        // The owner's signature, zero for deposits.
+        let withdraw_signature_target = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+            [private_key_target.clone(), vec![position_index_target, amount_target, nonce_target]].concat(),
+        );
+        let transfer_signature_target = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+            [
+                private_key_target.clone(),
+                vec![position_index_target, amount_target, nonce_target, to_position_index_target],
+            ]
+            .concat(),
+        );
+        let signature_target = select_hash(builder, is_transfer_target, transfer_signature_target, withdraw_signature_target);
+        let signature_target = select_hash(builder, is_deposit_target, zero_hash, signature_target);
+
+        transactions_hash_target = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+            [
+                transactions_hash_target.elements.to_vec(),
+                vec![transaction_type_target, position_index_target, amount_target, to_position_index_target],
+                signature_target.elements.to_vec(),
+            ]
+            .concat(),
+        );
+
+        root_target = root_after_target;
+        transactions.push(BatchTransactionTargets {
+            is_deposit_target,
+            is_withdraw_target,
+            is_transfer_target,
+            root_intermediate_target,
+            amount_target,
+            merkle_proof_target,
+            private_key_target,
+            pubkey_target,
+            balance_before_target,
+            nonce_target,
+            position_index_target,
+            to_merkle_proof_target,
+            to_pubkey_target,
+            to_balance_before_target,
+            to_nonce_target,
+            to_position_index_target,
+        });
+    }
+
+    builder.register_public_inputs(&root_before_target.elements);
+    builder.register_public_inputs(&root_target.elements);
+    builder.register_public_inputs(&transactions_hash_target.elements);
+
+    BatchTargets {
+        root_before_target,
+        root_after_target: root_target,
+        transactions_hash_target,
+        transactions,
+    }
+}
+
+/// The hash of a transaction list exposed by `batch_circuit`. Each transaction contributes its
+/// type, position, amount, receiving position (the same position unless it is a transfer) and
+/// the owner's signature (zero for deposits).
+pub fn batch_transactions_hash<F: RichField + Extendable<D>, const D: usize>(txs: &[Transaction<F, D>]) -> HashOut<F> {
+    let mut h = HashOut { elements: [F::ZERO; 4] };
+    for tx in txs.iter() {
+        let (to_position_index, signature) = match (tx.transaction_type, tx.private_key, &tx.transfer_to) {
+            (TransactionKind::Transfer, Some(private_key), Some(to)) => (
+                to.position_index,
+                transfer_signature(private_key, tx.position_index, tx.amount, tx.nonce, to.position_index),
+            ),
+            (TransactionKind::Withdraw, Some(private_key), _) => (
+                tx.position_index,
+                withdraw_signature(private_key, tx.position_index, tx.amount, tx.nonce),
+            ),
+            _ => (tx.position_index, HashOut { elements: [F::ZERO; 4] }),
+        };
+        let inputs = [
+            h.elements.to_vec(),
+            vec![
+                F::from_canonical_usize(tx.transaction_type as usize),
+                F::from_canonical_usize(tx.position_index),
+                tx.amount,
+                F::from_canonical_usize(to_position_index),
+            ],
+            signature.elements.to_vec(),
+        ]
+        .concat();
+        h = PoseidonHash::hash_no_pad(&inputs);
+    }
+    h
+}
+
+fn set_merkle_proof_target<F: RichField>(pw: &mut PartialWitness<F>, target: &MerkleProofTarget, siblings: &[HashOut<F>]) {
+    for (ht, h) in target.siblings.iter().zip(siblings.iter()) {
+        pw.set_hash_target(*ht, *h);
+    }
+}
+
+/// The second position of a slot: the receiver of a transfer, and the updated first position
+/// otherwise, which is proven by the same siblings against root_after.
+fn receiver<F: RichField + Extendable<D>, const D: usize>(tx: &Transaction<F, D>) -> TransferReceiver<F> {
+    match &tx.transfer_to {
+        Some(to) if tx.transaction_type == TransactionKind::Transfer => to.clone(),
+        _ => {
+            let (balance_after, nonce_after) = if tx.transaction_type == TransactionKind::Deposit {
+                (tx.balance_before + tx.amount, tx.nonce)
+            } else {
+                (tx.balance_before - tx.amount, tx.nonce + F::ONE)
+            };
+            TransferReceiver {
+                root_intermediate: tx.root_after,
+                merkle_proof: tx.merkle_proof.clone(),
+                pubkey: tx.pubkey,
+                balance_before: balance_after,
+                nonce: nonce_after,
+                position_index: tx.position_index,
+            }
+        }
+    }
+}
+
+fn set_sender_target<F: RichField + Extendable<D>, const D: usize>(pw: &mut PartialWitness<F>, t: &BatchTransactionTargets, tx: &Transaction<F, D>) {
+    pw.set_bool_target(t.is_deposit_target, tx.transaction_type == TransactionKind::Deposit);
+    pw.set_bool_target(t.is_withdraw_target, tx.transaction_type == TransactionKind::Withdraw);
+    pw.set_bool_target(t.is_transfer_target, tx.transaction_type == TransactionKind::Transfer);
+    pw.set_target(t.amount_target, tx.amount);
+
+    let private_key = tx.private_key.unwrap_or([F::ZERO; 4]);
+    for (&pt, k) in t.private_key_target.iter().zip(private_key) {
+        pw.set_target(pt, k);
+    }
+    pw.set_hash_target(t.pubkey_target, tx.pubkey);
+    pw.set_target(t.balance_before_target, tx.balance_before);
+    pw.set_target(t.nonce_target, tx.nonce);
+    pw.set_target(t.position_index_target, F::from_canonical_usize(tx.position_index));
+    set_merkle_proof_target(pw, &t.merkle_proof_target, &tx.merkle_proof.siblings);
+}
+
+fn set_receiver_target<F: RichField>(pw: &mut PartialWitness<F>, t: &BatchTransactionTargets, to: &TransferReceiver<F>) {
+    pw.set_hash_target(t.root_intermediate_target, to.root_intermediate);
+    pw.set_hash_target(t.to_pubkey_target, to.pubkey);
+    pw.set_target(t.to_balance_before_target, to.balance_before);
+    pw.set_target(t.to_nonce_target, to.nonce);
+    pw.set_target(t.to_position_index_target, F::from_canonical_usize(to.position_index));
+    set_merkle_proof_target(pw, &t.to_merkle_proof_target, &to.merkle_proof.siblings);
+}
+
+pub fn make_batch_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
+    config: &CircuitConfig,
+    txs: Vec<Transaction<F,D>>
+) ->Result<ProofTuple<F,C,D>>
+    where
+    [(); C::Hasher::HASH_SIZE]:,
+{
+    ensure!(!txs.is_empty(), "empty batch");
+    for pair in txs.windows(2) {
+        ensure!(pair[0].root_after == pair[1].root_before, "transactions do not chain");
+    }
+    let position_tree_depth = txs[0].position_tree_depth;
+    ensure!(
+        txs.iter().all(|tx| tx.position_tree_depth == position_tree_depth),
+        "transactions use different tree depths"
+    );
+
+    let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+    let batch_targets = batch_circuit::<F, C, D>(txs.len(), position_tree_depth, &mut builder);
+
+    let mut pw: PartialWitness<F> = PartialWitness::new();
+    pw.set_hash_target(batch_targets.root_before_target, txs[0].root_before);
+
+    for (t, tx) in batch_targets.transactions.iter().zip(txs.iter()) {
+        ensure!(
+            [TransactionKind::Deposit, TransactionKind::Withdraw, TransactionKind::Transfer].contains(&tx.transaction_type),
+            "invalid transaction type {:?}", tx.transaction_type
+        );
+        set_sender_target(&mut pw, t, tx);
+        set_receiver_target(&mut pw, t, &receiver(tx));
+    }
+
+    let data = builder.build::<C>();
+
+    let proof = data.prove(pw)?;
+    data.verify(proof.clone())?;
+    Ok((proof, data.verifier_only, data.common))
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+
+    use crate::recursive::recursive_proof;
+    use crate::sequencer::generate_transactions;
+    use crate::state::PositionState;
+    use crate::types::{public_key, F, C, D};
+    use super::*;
+
+    /// Proves `txs` with the given second positions, without the checks of `make_batch_proof`,
+    /// treating a panic during witness generation as a failure.
+    fn proves_unchecked(txs: &[Transaction<F, D>], receivers: &[TransferReceiver<F>]) -> bool {
+        let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+        let batch_targets = batch_circuit::<F, C, D>(txs.len(), txs[0].position_tree_depth, &mut builder);
+        let data = builder.build::<C>();
+
+        let mut pw = PartialWitness::new();
+        pw.set_hash_target(batch_targets.root_before_target, txs[0].root_before);
+        for ((t, tx), to) in batch_targets.transactions.iter().zip(txs).zip(receivers) {
+            set_sender_target(&mut pw, t, tx);
+            set_receiver_target(&mut pw, t, to);
+        }
+        panic::catch_unwind(AssertUnwindSafe(|| data.prove(pw))).map_or(false, |proof| proof.is_ok())
+    }
+
+
+    #[test]
+    fn test_batch_proof() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let txs = generate_transactions::<F, D>(vec![TransactionKind::Deposit, TransactionKind::Transfer, TransactionKind::Withdraw, TransactionKind::Deposit]).unwrap();
+        let transactions_hash = batch_transactions_hash(&txs);
+        let (root_before, root_after) = (txs[0].root_before, txs[3].root_after);
+
+        let (pi, vd, cd) = make_batch_proof::<F, C, D>(&standard_config, txs).unwrap();
+        assert_eq!(pi.public_inputs[0..4], root_before.elements);
+        assert_eq!(pi.public_inputs[4..8], root_after.elements);
+        assert_eq!(pi.public_inputs[8..12], transactions_hash.elements);
+
+        recursive_proof::<F, C, C, D>(pi, vd, cd, None, &standard_config, None).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_batch_rejects_reordered_transactions() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let mut txs = generate_transactions::<F, D>(vec![TransactionKind::Deposit, TransactionKind::Withdraw]).unwrap();
+        txs.swap(0, 1);
+        make_batch_proof::<F, C, D>(&standard_config, txs).unwrap();
+    }
+
+    #[test]
+    fn test_batch_circuit_rejects_reordered_transactions() {
+        let txs = generate_transactions::<F, D>(vec![TransactionKind::Deposit, TransactionKind::Withdraw]).unwrap();
+        let receivers: Vec<_> = txs.iter().map(receiver).collect();
+        assert!(proves_unchecked(&txs, &receivers));
+
+        let reordered = [txs[1].clone(), txs[0].clone()];
+        let receivers: Vec<_> = reordered.iter().map(receiver).collect();
+        assert!(!proves_unchecked(&reordered, &receivers));
+    }
+
+    #[test]
+    fn test_batch_circuit_constrains_receiver_of_non_transfers() {
+        let mut state = PositionState::<F>::new(4).unwrap();
+        state.open_position(3, public_key([F::ONE; 4]), F::from_canonical_u64(50)).unwrap();
+        state.open_position(4, public_key([F::TWO; 4]), F::from_canonical_u64(7)).unwrap();
+        let tx = state.apply::<D>(TransactionKind::Deposit, 3, F::ONE, None).unwrap();
+        assert!(proves_unchecked(&[tx.clone()], &[receiver(&tx)]));
+
+        // Credits zero to another untouched position, so only the hashed receiving index differs.
+        let other = state.position(4);
+        let to = TransferReceiver {
+            root_intermediate: tx.root_after,
+            merkle_proof: state.prove(4),
+            pubkey: other.pubkey,
+            balance_before: other.balance,
+            nonce: other.nonce,
+            position_index: 4,
+        };
+        assert!(!proves_unchecked(&[tx], &[to]));
+    }
+
+
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use plonky2_circom_verifier::estimator::estimate_circom_constraints;
+use plonky2_circom_verifier::formats::{proof_to_arkworks, proof_to_gnark_json, proof_to_le_bytes};
+use plonky2_circom_verifier::r1cs::{check_witness, R1cs};
+use plonky2_circom_verifier::security::SecurityLevel;
+use plonky2_circom_verifier::serialization::{read_common_data, read_proof, read_verifier_data};
+use plonky2_circom_verifier::stats::ProofStats;
+use plonky2_circom_verifier::types::{Cbn128, D, F};
+use plonky2_circom_verifier::verifier::{
+    generate_circom_verifier, generate_proof_for_circom, generate_solidity_wrapper,
+    verifier_config_from_common,
+};
+use plonky2_circom_verifier::witness::{read_wtns, write_wtns, SymbolTable, WitnessCalculator};
+
+const USAGE: &str = "usage:
+    plonky2-circom gen-verifier --common <file> --verifier-data <file> --out <dir>
+        [--min-security-bits <bits>] [--compress-public-inputs true|false]
+    plonky2-circom gen-input --common <file> --proof <file> [--out <file>]
+        [--format circom|binary|gnark|arkworks]
+    plonky2-circom gen-witness --wasm <file> --sym <file> --input <file> [--out <file>]
+    plonky2-circom check-witness --r1cs <file> --wtns <file> --sym <file>
+    plonky2-circom inspect --common <file> [--proof <file>]
+
+The inputs are the binary files written by the `serialization` module, for a circuit built with
+PoseidonBN128GoldilocksConfig.";
+
+fn parse_flags(args: &[String]) -> Result<HashMap<String, String>> {
+    let mut flags = HashMap::new();
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let name = flag
+            .strip_prefix("--")
+            .ok_or_else(|| anyhow!("unexpected argument `{}`", flag))?;
+        let value = args
+            .next()
+            .ok_or_else(|| anyhow!("missing value for `{}`", flag))?;
+        flags.insert(name.to_string(), value.clone());
+    }
+    Ok(flags)
+}
+
+fn required<'a>(flags: &'a HashMap<String, String>, name: &str) -> Result<&'a String> {
+    flags
+        .get(name)
+        .ok_or_else(|| anyhow!("missing required flag `--{}`", name))
+}
+
+fn gen_verifier(flags: &HashMap<String, String>) -> Result<()> {
+    let common = read_common_data::<F, D, _>(required(flags, "common")?)?;
+    let verifier_only = read_verifier_data::<F, Cbn128, D, _>(required(flags, "verifier-data")?)?;
+    let out = PathBuf::from(required(flags, "out")?);
+
+    let min_security_bits = match flags.get("min-security-bits") {
+        Some(min_bits) => Some(min_bits.parse()?),
+        None => None,
+    };
+
+    let mut conf = verifier_config_from_common(&common)?;
+    if let Some(compress) = flags.get("compress-public-inputs") {
+        conf.compress_public_inputs = compress.parse()?;
+    }
+    let (circom_constants, circom_gates, _) =
+        generate_circom_verifier(&conf, &common, &verifier_only, min_security_bits)?;
+
+    fs::create_dir_all(&out)?;
+    fs::write(out.join("constants.circom"), circom_constants)?;
+    fs::write(out.join("gates.circom"), circom_gates)?;
+    fs::write(out.join("conf.json"), serde_json::to_string(&conf)?)?;
+    if conf.num_public_inputs > 0 {
+        let (wrapper, abi) = generate_solidity_wrapper(&conf)?;
+        fs::write(out.join("Plonky2Verifier.sol"), wrapper)?;
+        fs::write(out.join("Plonky2Verifier.abi.json"), abi)?;
+    }
+    println!("Wrote the Circom verifier to {}", out.display());
+    Ok(())
+}
+
+fn gen_input(flags: &HashMap<String, String>) -> Result<()> {
+    let common = read_common_data::<F, D, _>(required(flags, "common")?)?;
+    let proof = read_proof::<F, Cbn128, D, _>(required(flags, "proof")?, &common)?;
+    let out = flags.get("out").map(String::as_str).unwrap_or("proof.json");
+
+    let conf = verifier_config_from_common(&common)?;
+    let format = flags.get("format").map(String::as_str).unwrap_or("circom");
+    let circom_proof = generate_proof_for_circom(&proof, &conf)?;
+    match format {
+        "circom" => fs::write(out, serde_json::to_string(&circom_proof)?)?,
+        "binary" => fs::write(out, proof_to_le_bytes(&circom_proof, &conf)?)?,
+        "gnark" => fs::write(out, proof_to_gnark_json(&circom_proof, &conf)?)?,
+        "arkworks" => {
+            let ark_proof = proof_to_arkworks(&circom_proof, &conf)?;
+            fs::write(out, serde_json::to_string(&ark_proof)?)?
+        }
+        _ => bail!("unknown format `{}`", format),
+    }
+    println!("Wrote the {} input to {}", format, out);
+    Ok(())
+}
+
+fn gen_witness(flags: &HashMap<String, String>) -> Result<()> {
+    let calculator = WitnessCalculator::new(required(flags, "wasm")?)?;
+    let sym = SymbolTable::read(required(flags, "sym")?)?;
+    let input = fs::read_to_string(required(flags, "input")?)?;
+    let out = flags.get("out").map(String::as_str).unwrap_or("witness.wtns");
+
+    let witness = calculator.calculate(&sym, &input)?;
+    write_wtns(out, &calculator.prime, &witness)?;
+    println!("Wrote {} witness values to {}", witness.len(), out);
+    Ok(())
+}
+
+fn check(flags: &HashMap<String, String>) -> Result<()> {
+    let mut r1cs = R1cs::read(required(flags, "r1cs")?)?;
+    let (prime, witness) = read_wtns(required(flags, "wtns")?)?;
+    let sym = SymbolTable::read(required(flags, "sym")?)?;
+    if prime != r1cs.prime {
+        bail!("the witness and the circuit are over different fields");
+    }
+
+    match check_witness(&mut r1cs, &witness, &sym)? {
+        Some(unsatisfied) => bail!("{}", unsatisfied),
+        None => {
+            println!("All {} constraints are satisfied", r1cs.num_constraints);
+            Ok(())
+        }
+    }
+}
+
+fn inspect(flags: &HashMap<String, String>) -> Result<()> {
+    let common = read_common_data::<F, D, _>(required(flags, "common")?)?;
+    let conf = verifier_config_from_common(&common)?;
+    let estimate = estimate_circom_constraints(&conf, &common);
+
+    println!("degree_bits: {}", common.degree_bits());
+    println!("num_public_inputs: {}", common.num_public_inputs);
+    println!(
+        "reduction_arity_bits: {:?}",
+        common.fri_params.reduction_arity_bits
+    );
+    println!("estimated_circom_constraints: {}", estimate.total());
+    let level = SecurityLevel::from_common(&common);
+    println!(
+        "security_bits: {:.1} conjectured, {:.1} proven",
+        level.conjectured_bits(),
+        level.proven_bits()
+    );
+    println!("{}", serde_json::to_string_pretty(&level)?);
+    println!("{}", serde_json::to_string_pretty(&conf)?);
+    if let Some(path) = flags.get("proof") {
+        let proof = read_proof::<F, Cbn128, D, _>(path, &common)?;
+        println!("{}", serde_json::to_string_pretty(&ProofStats::new(&proof, &conf))?);
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some((command, rest)) = args.split_first() else {
+        bail!("{}", USAGE);
+    };
+    let flags = parse_flags(rest)?;
+    match command.as_str() {
+        "gen-verifier" => gen_verifier(&flags),
+        "gen-input" => gen_input(&flags),
+        "gen-witness" => gen_witness(&flags),
+        "check-witness" => check(&flags),
+        "inspect" => inspect(&flags),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => bail!("unknown command `{}`\n\n{}", command, USAGE),
+    }
+}
@@ -26,6 +26,7 @@ use plonky2::plonk::config::Hasher;
 use plonky2::plonk::proof::ProofWithPublicInputs;
 use plonky2::plonk::prover::prove;
 use plonky2::util::timing::TimingTree;
+use crate::types::BALANCE_BITS;
 use crate::types::POSITION_TREE_DEPTH;
 use crate::types::ProofTuple;
 use crate::types::Transaction;
@@ -51,7 +52,9 @@ pub struct DepositTargets {
     pub root_before_target: HashOutTarget,
     pub root_after_target: HashOutTarget,
     pub merkle_proof_target: MerkleProofTarget,
+    pub pubkey_target: HashOutTarget,
     pub balance_before_target: Target,
+    pub nonce_target: Target,
     pub position_index_target: Target,
     pub amount_target: Target,
 }
@@ -65,23 +68,32 @@ pub fn deposit_circuit<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>
         siblings: builder.add_virtual_hashes(tree_height),
     };
 
+    // Anyone may deposit into a position, so the owner's key and nonce are kept as they are.
+    let pubkey_target = builder.add_virtual_hash();
     let balance_before_target = builder.add_virtual_target();
+    let nonce_target = builder.add_virtual_target();
 
     let position_index_target = builder.add_virtual_target();
     let position_index_bits = builder.split_le(position_index_target, tree_height);
     
     builder.verify_merkle_proof_to_cap::<PoseidonHash>(
-        vec![balance_before_target],
+        [pubkey_target.elements.to_vec(), vec![balance_before_target, nonce_target]].concat(),
         &position_index_bits,
         &MerkleCapTarget(vec![root_before_target]),
         &merkle_proof_target,
     );
 
+    builder.range_check(balance_before_target, BALANCE_BITS);
+
     let amount_target = builder.add_virtual_target();
+    builder.range_check(amount_target, BALANCE_BITS);
+    // Balances and amounts are kept below 2^BALANCE_BITS, so the sum is below 2^63 and cannot
+    // wrap around the field order.
     let balance_after_target = builder.add(balance_before_target, amount_target);
+    builder.range_check(balance_after_target, BALANCE_BITS);
 
     builder.verify_merkle_proof_to_cap::<PoseidonHash>(
-        vec![balance_after_target],
+        [pubkey_target.elements.to_vec(), vec![balance_after_target, nonce_target]].concat(),
         &position_index_bits,
         &MerkleCapTarget(vec![root_after_target]),
         &merkle_proof_target,
@@ -94,7 +106,9 @@ pub fn deposit_circuit<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>
         root_before_target,
         root_after_target,
         merkle_proof_target,
+        pubkey_target,
         balance_before_target,
+        nonce_target,
         position_index_target,
         amount_target,
     }
@@ -109,12 +123,14 @@ pub fn make_deposit_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F =
 {
 
     let mut builder = CircuitBuilder::<F, D>::new(config.clone());
-    let deposit_targets = deposit_circuit::<F, C, D>(POSITION_TREE_DEPTH, &mut builder);
+    let deposit_targets = deposit_circuit::<F, C, D>(deposit_tx.position_tree_depth, &mut builder);
     let DepositTargets {
         root_before_target,
         root_after_target,
         merkle_proof_target,
+        pubkey_target,
         balance_before_target,
+        nonce_target,
         position_index_target,
         amount_target,
     } = deposit_targets;
@@ -124,17 +140,23 @@ pub fn make_deposit_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F =
         root_before,
         root_after,
         merkle_proof,
+        pubkey,
         balance_before,
+        nonce,
         position_index,
         amount,
         position_tree_depth,
+        private_key,
+        transfer_to,
     } = deposit_tx;
 
     let mut pw: PartialWitness<F> = PartialWitness::new();
     pw.set_hash_target(root_before_target, root_before);
     pw.set_hash_target(root_after_target, root_after);
 
+    pw.set_hash_target(pubkey_target, pubkey);
     pw.set_target(balance_before_target, balance_before);
+    pw.set_target(nonce_target, nonce);
     pw.set_target(position_index_target, F::from_canonical_usize(position_index));
     pw.set_target(amount_target, amount);
 
@@ -166,9 +188,9 @@ mod tests {
     use plonky2::{plonk::config::{GenericConfig, PoseidonGoldilocksConfig}, fri::{FriConfig, reduction_strategies::FriReductionStrategy}};
 
     // use crate::{config::KeccakGoldilocksConfig2};
-    use crate::{verifier::{generate_proof_base64, generate_verifier_config, generate_circom_verifier}, types::{verify_proof, Cbn128}, sequencer::generate_transactions};
+    use crate::{verifier::{generate_proof_base64, generate_verifier_config, generate_circom_verifier}, types::{verify_proof, Cbn128}, sequencer::{generate_transactions, root_from_merkle_proof}};
     use crate::recursive::recursive_proof;
-    use crate::types::{F, C, D};
+    use crate::types::{F, C, D, Position, TransactionKind};
     use super::*;
 
 
@@ -182,12 +204,60 @@ mod tests {
     #[test]
     fn test_deposit_proof() {
         let standard_config = CircuitConfig::standard_recursion_config();
-        let deposit_tx = generate_transactions::<F, D>(vec![0]).unwrap();
+        let deposit_tx = generate_transactions::<F, D>(vec![TransactionKind::Deposit]).unwrap();
         let (pi, vd, cd) = 
             make_deposit_proof::<F, C, D>(&standard_config, deposit_tx[0].clone()).unwrap();
         verify_proof(pi, vd, cd).unwrap();
     }
 
+    #[test]
+    #[should_panic]
+    fn test_deposit_amount_out_of_range() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let mut deposit_tx = generate_transactions::<F, D>(vec![TransactionKind::Deposit]).unwrap()[0].clone();
+        deposit_tx.amount = F::from_canonical_u64(1 << BALANCE_BITS);
+        let position = Position {
+            pubkey: deposit_tx.pubkey,
+            balance: deposit_tx.balance_before + deposit_tx.amount,
+            nonce: deposit_tx.nonce,
+        };
+        deposit_tx.root_after = root_from_merkle_proof(
+            position.to_leaf(),
+            deposit_tx.position_index,
+            &deposit_tx.merkle_proof,
+        );
+        make_deposit_proof::<F, C, D>(&standard_config, deposit_tx).unwrap();
+    }
 
-}
+    #[test]
+    #[should_panic]
+    fn test_deposit_sum_out_of_range() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let mut deposit_tx = generate_transactions::<F, D>(vec![TransactionKind::Deposit]).unwrap()[0].clone();
+        // A balance and an amount which both pass their own range checks.
+        deposit_tx.balance_before = F::from_canonical_u64((1 << BALANCE_BITS) - 1);
+        deposit_tx.amount = F::from_canonical_u64((1 << BALANCE_BITS) - 1);
+        let position = Position {
+            pubkey: deposit_tx.pubkey,
+            balance: deposit_tx.balance_before,
+            nonce: deposit_tx.nonce,
+        };
+        deposit_tx.root_before = root_from_merkle_proof(
+            position.to_leaf(),
+            deposit_tx.position_index,
+            &deposit_tx.merkle_proof,
+        );
+        let position = Position {
+            balance: deposit_tx.balance_before + deposit_tx.amount,
+            ..position
+        };
+        deposit_tx.root_after = root_from_merkle_proof(
+            position.to_leaf(),
+            deposit_tx.position_index,
+            &deposit_tx.merkle_proof,
+        );
+        make_deposit_proof::<F, C, D>(&standard_config, deposit_tx).unwrap();
+    }
 
+
+}
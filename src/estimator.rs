@@ -0,0 +1,246 @@
+use plonky2::field::extension::Extendable;
+use plonky2::hash::hash_types::RichField;
+use plonky2::plonk::circuit_data::CommonCircuitData;
+use serde::Serialize;
+
+use crate::verifier::VerifierConfig;
+
+// Non-linear constraints of the Circom templates used by the verifier. A `GlReduce(N)` costs
+// N + 64 constraints for the two range checks, plus one for the product feeding it.
+pub const GL_ADD_CONSTRAINTS: usize = 65;
+pub const GL_MUL_CONSTRAINTS: usize = 129;
+pub const GL_EXT_MUL_CONSTRAINTS: usize = 5 * GL_MUL_CONSTRAINTS + 2 * GL_ADD_CONSTRAINTS;
+pub const GL_EXT_DIV_CONSTRAINTS: usize = 2 * GL_EXT_MUL_CONSTRAINTS;
+// PoseidonEx(4, 4) plus the four Num2Bits(254) splitting the state back into Goldilocks limbs.
+pub const POSEIDON_BN_CONSTRAINTS: usize = 300 + 4 * 254;
+// 118 S-boxes of three GlReduce(66) each, and 30 MDS layers of twelve GlReduce(74).
+pub const POSEIDON_GL_CONSTRAINTS: usize = 118 * (3 * 131 + 1) + 30 * 12 * 138;
+
+// The two LessNBits(64) of `GlCanonical`, checking each public input.
+pub const GL_CANONICAL_CONSTRAINTS: usize = 2 * 64;
+
+const SPONGE_RATE: usize = 8;
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct ConstraintEstimate {
+    pub poseidon_bn: usize,
+    pub poseidon_gl: usize,
+    pub fri_queries: usize,
+    pub gates: Vec<(String, usize)>,
+    pub eval_vanishing_poly: usize,
+    pub check_zeta: usize,
+}
+
+impl ConstraintEstimate {
+    pub fn total(&self) -> usize {
+        self.poseidon_bn
+            + self.poseidon_gl
+            + self.fri_queries
+            + self.gates.iter().map(|(_, c)| c).sum::<usize>()
+            + self.eval_vanishing_poly
+            + self.check_zeta
+    }
+}
+
+fn num_hashes(num_leaf: usize, num_siblings: usize) -> usize {
+    (num_leaf + SPONGE_RATE - 1) / SPONGE_RATE + num_siblings
+}
+
+/// Number of BN128 Poseidon permutations used by `GetChallenges`.
+fn challenger_permutations(conf: &VerifierConfig) -> usize {
+    let num_openings = conf.num_openings_constants
+        + conf.num_openings_plonk_sigmas
+        + conf.num_openings_wires
+        + conf.num_openings_plonk_zs
+        + conf.num_openings_plonk_zs_next
+        + conf.num_openings_partial_products
+        + conf.num_openings_quotient_polys;
+    let num_observed = 4
+        + 4
+        + 4 * (conf.num_wires_cap + conf.num_plonk_zs_partial_products_cap)
+        + 4 * conf.num_quotient_polys_cap
+        + 2 * num_openings
+        + 4 * conf.num_fri_commit_round * conf.fri_commit_merkle_cap_height
+        + 2 * conf.num_fri_final_poly_ext_v
+        + 1;
+    // Every observe batch ends with a squeeze, and the query indices are squeezed separately.
+    let num_batches = 7 + conf.num_fri_commit_round;
+    (num_observed + SPONGE_RATE - 1) / SPONGE_RATE
+        + num_batches
+        + (conf.num_fri_query_round + SPONGE_RATE - 1) / SPONGE_RATE
+}
+
+/// Number of BN128 Poseidon permutations used by the Merkle proofs of a single FRI query.
+fn query_round_permutations(conf: &VerifierConfig) -> usize {
+    num_hashes(
+        conf.num_fri_query_init_constants_sigmas_v,
+        conf.num_fri_query_init_constants_sigmas_p,
+    ) + num_hashes(
+        conf.num_fri_query_init_wires_v,
+        conf.num_fri_query_init_wires_p,
+    ) + num_hashes(
+        conf.num_fri_query_init_zs_partial_v,
+        conf.num_fri_query_init_zs_partial_p,
+    ) + num_hashes(
+        conf.num_fri_query_init_quotient_v,
+        conf.num_fri_query_init_quotient_p,
+    ) + num_hashes(2 * conf.num_fri_query_step0_v, conf.num_fri_query_step0_p)
+        + num_hashes(2 * conf.num_fri_query_step1_v, conf.num_fri_query_step1_p)
+}
+
+/// Predicts the non-linear constraints of the Circom verifier generated for `conf` and `common`,
+/// broken down per component. The figures are derived from the Circom templates and are meant
+/// for budgeting a config, not as an exact replacement for `circom --r1cs`.
+pub fn estimate_circom_constraints<F: RichField + Extendable<D>, const D: usize>(
+    conf: &VerifierConfig,
+    common: &CommonCircuitData<F, D>,
+) -> ConstraintEstimate {
+    let num_challenges = common.config.num_challenges;
+
+    let poseidon_bn = (challenger_permutations(conf)
+        + conf.num_fri_query_round * query_round_permutations(conf))
+        * POSEIDON_BN_CONSTRAINTS;
+    // Hashing the public inputs, each checked to be canonical first.
+    let poseidon_gl = (conf.num_public_inputs + SPONGE_RATE - 1) / SPONGE_RATE
+        * POSEIDON_GL_CONSTRAINTS
+        + conf.num_public_inputs * GL_CANONICAL_CONSTRAINTS;
+
+    // Each query combines every initial opening with alpha, divides by (x - zeta) and
+    // (x - g * zeta), folds each step by interpolation, evaluates the final polynomial and
+    // computes x from the query index.
+    let init_evals = conf.num_fri_query_init_constants_sigmas_v
+        + conf.num_fri_query_init_wires_v
+        + conf.num_fri_query_init_zs_partial_v
+        + conf.num_fri_query_init_quotient_v;
+    let log_n = common.degree_bits() + common.config.fri_config.rate_bits;
+    let query_round = init_evals * GL_EXT_MUL_CONSTRAINTS
+        + 2 * GL_EXT_DIV_CONSTRAINTS
+        + 3 * (conf.num_fri_query_step0_v + conf.num_fri_query_step1_v) * GL_EXT_MUL_CONSTRAINTS
+        + conf.num_fri_final_poly_ext_v * GL_EXT_MUL_CONSTRAINTS
+        + 2 * log_n * GL_MUL_CONSTRAINTS;
+    let fri_queries = conf.num_fri_query_round * query_round + 64;
+
+    let num_selectors = common.selectors_info.num_selectors();
+    let gates = common
+        .gates
+        .iter()
+        .enumerate()
+        .filter(|(_, gate)| !gate.0.id().eq("NoopGate"))
+        .map(|(row, gate)| {
+            let selector_index = common.selectors_info.selector_indices[row];
+            let filter_len =
+                common.selectors_info.groups[selector_index].len() + usize::from(num_selectors > 1);
+            let constraints = gate.0.num_constraints() * gate.0.degree() + filter_len;
+            (gate.0.id(), constraints * GL_EXT_MUL_CONSTRAINTS)
+        })
+        .collect();
+
+    // The permutation argument evaluates a numerator and a denominator per routed wire, and
+    // multiplies the partial products in chunks, once per challenge. L_1(zeta) needs zeta^n.
+    let eval_vanishing_poly = num_challenges
+        * (4 * common.config.num_routed_wires + 2 * common.num_partial_products + 4)
+        * GL_EXT_MUL_CONSTRAINTS
+        + common.degree_bits() * GL_EXT_MUL_CONSTRAINTS
+        + GL_EXT_DIV_CONSTRAINTS;
+
+    let check_zeta = num_challenges
+        * (common.num_gate_constraints + common.num_partial_products + 1)
+        * GL_EXT_MUL_CONSTRAINTS
+        + conf.num_openings_quotient_polys * GL_EXT_MUL_CONSTRAINTS;
+
+    ConstraintEstimate {
+        poseidon_bn,
+        poseidon_gl,
+        fri_queries,
+        gates,
+        eval_vanishing_poly,
+        check_zeta,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::fri::reduction_strategies::FriReductionStrategy;
+    use plonky2::fri::FriConfig;
+    use plonky2::plonk::circuit_data::CircuitConfig;
+
+    use crate::deposit::make_deposit_proof;
+    use crate::recursive::recursive_proof;
+    use crate::sequencer::generate_transactions;
+    use crate::test_utils::{dummy_proof, final_config, high_rate_config};
+    use crate::types::{Cbn128, TransactionKind, C, D, F};
+    use crate::verifier::generate_verifier_config;
+
+    use super::*;
+
+    #[test]
+    fn test_estimate_grows_with_query_rounds() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let deposit_tx = generate_transactions::<F, D>(vec![TransactionKind::Deposit]).unwrap();
+        let (pi, vd, cd) =
+            make_deposit_proof::<F, C, D>(&standard_config, deposit_tx[0].clone()).unwrap();
+
+        let mut totals = Vec::new();
+        for num_query_rounds in [10, 20] {
+            // The final config of `test_resursive_single_proof_to_circom`.
+            let config = CircuitConfig {
+                num_routed_wires: 37,
+                fri_config: FriConfig {
+                    rate_bits: 8,
+                    cap_height: 0,
+                    proof_of_work_bits: 20,
+                    reduction_strategy: FriReductionStrategy::MinSize(None),
+                    num_query_rounds,
+                },
+                ..standard_config.clone()
+            };
+            let (proof, _, common) = recursive_proof::<F, Cbn128, C, D>(
+                pi.clone(),
+                vd.clone(),
+                cd.clone(),
+                None,
+                &config,
+                None,
+            )
+            .unwrap();
+            let conf = generate_verifier_config(&proof).unwrap();
+            let estimate = estimate_circom_constraints(&conf, &common);
+            assert!(estimate.poseidon_bn > 0);
+            assert!(!estimate.gates.is_empty());
+            totals.push(estimate.total());
+        }
+        assert!(totals[0] < totals[1]);
+    }
+
+    #[test]
+    fn test_estimate_matches_readme() {
+        // The pipeline of `test_recursive_verifier`, whose Circom verifier the README reports at
+        // 12,892,923 non-linear constraints.
+        const README_CONSTRAINTS: usize = 12_892_923;
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let (proof, vd, cd) = dummy_proof::<F, C, D>(&standard_config, 4_000, 4).unwrap();
+        let (proof, vd, cd) = recursive_proof::<F, C, C, D>(
+            proof,
+            vd,
+            cd,
+            None,
+            &high_rate_config(),
+            None,
+        )
+        .unwrap();
+        let (proof, _, common) = recursive_proof::<F, Cbn128, C, D>(
+            proof,
+            vd,
+            cd,
+            None,
+            &final_config(),
+            None,
+        )
+        .unwrap();
+
+        let conf = generate_verifier_config(&proof).unwrap();
+        let total = estimate_circom_constraints(&conf, &common).total();
+        let error = (total as f64 - README_CONSTRAINTS as f64).abs() / README_CONSTRAINTS as f64;
+        assert!(error < 0.25, "estimated {} constraints", total);
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::{anyhow, ensure, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::verifier::{ProofForCircom, VerifierConfig};
+
+// Other encodings of `ProofForCircom`, for Groth16 circuits written with gnark or arkworks.
+
+/// The input signals of `VerifyPlonky2Proof` and their dimensions, in declaration order.
+pub fn circom_input_shapes(conf: &VerifierConfig) -> Vec<(&'static str, Vec<usize>)> {
+    let q = conf.num_fri_query_round;
+    vec![
+        ("wires_cap", vec![conf.num_wires_cap, 4]),
+        ("plonk_zs_partial_products_cap", vec![conf.num_plonk_zs_partial_products_cap, 4]),
+        ("quotient_polys_cap", vec![conf.num_quotient_polys_cap, 4]),
+        ("openings_constants", vec![conf.num_openings_constants, 2]),
+        ("openings_plonk_sigmas", vec![conf.num_openings_plonk_sigmas, 2]),
+        ("openings_wires", vec![conf.num_openings_wires, 2]),
+        ("openings_plonk_zs", vec![conf.num_openings_plonk_zs, 2]),
+        ("openings_plonk_zs_next", vec![conf.num_openings_plonk_zs_next, 2]),
+        ("openings_partial_products", vec![conf.num_openings_partial_products, 2]),
+        ("openings_quotient_polys", vec![conf.num_openings_quotient_polys, 2]),
+        (
+            "fri_commit_phase_merkle_caps",
+            vec![conf.num_fri_commit_round, conf.fri_commit_merkle_cap_height, 4],
+        ),
+        ("fri_query_init_constants_sigmas_v", vec![q, conf.num_fri_query_init_constants_sigmas_v]),
+        (
+            "fri_query_init_constants_sigmas_p",
+            vec![q, conf.num_fri_query_init_constants_sigmas_p, 4],
+        ),
+        ("fri_query_init_wires_v", vec![q, conf.num_fri_query_init_wires_v]),
+        ("fri_query_init_wires_p", vec![q, conf.num_fri_query_init_wires_p, 4]),
+        ("fri_query_init_zs_partial_v", vec![q, conf.num_fri_query_init_zs_partial_v]),
+        ("fri_query_init_zs_partial_p", vec![q, conf.num_fri_query_init_zs_partial_p, 4]),
+        ("fri_query_init_quotient_v", vec![q, conf.num_fri_query_init_quotient_v]),
+        ("fri_query_init_quotient_p", vec![q, conf.num_fri_query_init_quotient_p, 4]),
+        ("fri_query_step0_v", vec![q, conf.num_fri_query_step0_v, 2]),
+        ("fri_query_step0_p", vec![q, conf.num_fri_query_step0_p, 4]),
+        ("fri_query_step1_v", vec![q, conf.num_fri_query_step1_v, 2]),
+        ("fri_query_step1_p", vec![q, conf.num_fri_query_step1_p, 4]),
+        ("fri_final_poly_ext_v", vec![conf.num_fri_final_poly_ext_v, 2]),
+        ("fri_pow_witness", vec![]),
+        ("public_inputs", vec![conf.num_public_inputs]),
+    ]
+}
+
+fn flatten(name: &str, value: &Value, shape: &[usize], out: &mut Vec<u64>) -> Result<()> {
+    match (value, shape.split_first()) {
+        (Value::String(s), None) => {
+            out.push(s.parse().map_err(|_| anyhow!("`{}` in {} is not a u64", s, name))?);
+        }
+        (Value::Array(values), Some((len, rest))) => {
+            ensure!(
+                values.len() == *len,
+                "{} has {} rows where the config expects {}",
+                name,
+                values.len(),
+                len
+            );
+            for v in values {
+                flatten(name, v, rest, out)?;
+            }
+        }
+        _ => return Err(anyhow!("{} does not have the shape {:?}", name, shape)),
+    }
+    Ok(())
+}
+
+/// Every input signal with its shape and its elements in row-major order.
+pub fn flatten_signals(
+    proof: &ProofForCircom,
+    conf: &VerifierConfig,
+) -> Result<Vec<(&'static str, Vec<usize>, Vec<u64>)>> {
+    let fields = match serde_json::to_value(proof)? {
+        Value::Object(fields) => fields,
+        _ => unreachable!(),
+    };
+    circom_input_shapes(conf)
+        .into_iter()
+        .map(|(name, shape)| {
+            let mut values = vec![];
+            flatten(name, &fields[name], &shape, &mut values)?;
+            Ok((name, shape, values))
+        })
+        .collect()
+}
+
+/// Every field element of the proof as a little-endian u64, in the order of
+/// `circom_input_shapes`.
+pub fn proof_to_le_bytes(proof: &ProofForCircom, conf: &VerifierConfig) -> Result<Vec<u8>> {
+    Ok(flatten_signals(proof, conf)?
+        .into_iter()
+        .flat_map(|(_, _, values)| values)
+        .flat_map(u64::to_le_bytes)
+        .collect())
+}
+
+// gnark assigns the witness through exported struct fields, e.g. `WiresCap`.
+fn gnark_field_name(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(c) => c.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// A gnark witness assignment, with one field per input signal and `PublicInputs` as the only
+/// public one.
+pub fn proof_to_gnark_json(proof: &ProofForCircom, conf: &VerifierConfig) -> Result<String> {
+    let fields = match serde_json::to_value(proof)? {
+        Value::Object(fields) => fields,
+        _ => unreachable!(),
+    };
+    let mut assignment = Map::new();
+    for (name, shape) in circom_input_shapes(conf) {
+        flatten(name, &fields[name], &shape, &mut vec![])?;
+        assignment.insert(gnark_field_name(name), fields[name].clone());
+    }
+    Ok(serde_json::to_string(&assignment)?)
+}
+
+/// The proof split into the public and private inputs of a Groth16 circuit. `layout` gives the
+/// name and length of every signal in `private_inputs`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArkworksProof {
+    pub public_inputs: Vec<u64>,
+    pub private_inputs: Vec<u64>,
+    pub layout: Vec<(String, usize)>,
+}
+
+impl ArkworksProof {
+    /// The public inputs in the circuit field, e.g. `ark_bn254::Fr`.
+    pub fn public_inputs_as<Fr: From<u64>>(&self) -> Vec<Fr> {
+        self.public_inputs.iter().map(|v| Fr::from(*v)).collect()
+    }
+
+    pub fn private_inputs_as<Fr: From<u64>>(&self) -> Vec<Fr> {
+        self.private_inputs.iter().map(|v| Fr::from(*v)).collect()
+    }
+}
+
+pub fn proof_to_arkworks(proof: &ProofForCircom, conf: &VerifierConfig) -> Result<ArkworksProof> {
+    let mut ark_proof = ArkworksProof {
+        public_inputs: vec![],
+        private_inputs: vec![],
+        layout: vec![],
+    };
+    for (name, _, values) in flatten_signals(proof, conf)? {
+        if name == "public_inputs" {
+            ark_proof.public_inputs = values;
+        } else {
+            ark_proof.layout.push((name.to_string(), values.len()));
+            ark_proof.private_inputs.extend(values);
+        }
+    }
+    Ok(ark_proof)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use plonky2::field::types::PrimeField64;
+    use plonky2::plonk::config::GenericHashOut;
+
+    use crate::test_utils::{dummy_proof, final_config};
+    use crate::types::{Cbn128, D, F};
+    use crate::verifier::{generate_proof_for_circom, verifier_config_from_common};
+
+    use super::*;
+
+    #[test]
+    fn test_formats() -> Result<()> {
+        let (pwpi, _, common) = dummy_proof::<F, Cbn128, D>(&final_config(), 4_000, 2)?;
+
+        let conf = verifier_config_from_common(&common)?;
+        let proof = generate_proof_for_circom(&pwpi, &conf)?;
+        let num_elements: usize = circom_input_shapes(&conf)
+            .iter()
+            .map(|(_, shape)| shape.iter().product::<usize>())
+            .sum();
+
+        let bytes = proof_to_le_bytes(&proof, &conf)?;
+        assert_eq!(bytes.len(), 8 * num_elements);
+        let first = pwpi.proof.wires_cap.0[0].to_vec()[0];
+        assert_eq!(bytes[..8], first.to_canonical_u64().to_le_bytes());
+
+        let gnark: Value = serde_json::from_str(&proof_to_gnark_json(&proof, &conf)?)?;
+        assert_eq!(gnark["PublicInputs"][1], "1");
+        assert_eq!(gnark["FriPowWitness"], proof.fri_pow_witness.as_str());
+
+        let ark_proof = proof_to_arkworks(&proof, &conf)?;
+        assert_eq!(ark_proof.public_inputs, vec![0, 1]);
+        assert_eq!(ark_proof.private_inputs.len() + 2, num_elements);
+        assert_eq!(ark_proof.layout[0], ("wires_cap".to_string(), 4));
+
+        let mut truncated = proof;
+        truncated.public_inputs.pop();
+        assert!(proof_to_le_bytes(&truncated, &conf).is_err());
+        Ok(())
+    }
+}
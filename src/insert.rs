@@ -0,0 +1,149 @@
+use anyhow::Ok;
+use anyhow::Result;
+use plonky2::field::extension::Extendable;
+use plonky2::field::types::Field;
+use plonky2::hash::hash_types::HashOutTarget;
+use plonky2::hash::hash_types::MerkleCapTarget;
+use plonky2::hash::hash_types::RichField;
+use plonky2::hash::merkle_proofs::MerkleProofTarget;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::Target;
+use plonky2::iop::witness::PartialWitness;
+use plonky2::iop::witness::WitnessWrite;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::circuit_data::CircuitConfig;
+use plonky2::plonk::config::GenericConfig;
+use plonky2::plonk::config::Hasher;
+use crate::types::Insertion;
+use crate::types::ProofTuple;
+
+
+#[derive(Debug, Clone)]
+pub struct InsertTargets {
+    pub root_before_target: HashOutTarget,
+    pub root_after_target: HashOutTarget,
+    pub merkle_proof_target: MerkleProofTarget,
+    pub pubkey_target: HashOutTarget,
+    pub position_index_target: Target,
+}
+
+/// Opens a new position. The leaf must be empty under `root_before`, which is the
+/// non-membership proof of `SparseMerkleTree`, and holds the new public key with a zero balance
+/// and nonce under `root_after`. The public inputs are both roots and the public key.
+pub fn insert_circuit<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> , const D: usize>(tree_height: usize, builder: &mut CircuitBuilder<F, D>) -> InsertTargets {
+    let root_before_target = builder.add_virtual_hash();
+
+    let root_after_target = builder.add_virtual_hash();
+
+    let merkle_proof_target = MerkleProofTarget {
+        siblings: builder.add_virtual_hashes(tree_height),
+    };
+
+    let pubkey_target = builder.add_virtual_hash();
+
+    let position_index_target = builder.add_virtual_target();
+    let position_index_bits = builder.split_le(position_index_target, tree_height);
+
+    let zero = builder.zero();
+    builder.verify_merkle_proof_to_cap::<PoseidonHash>(
+        vec![zero; 6],
+        &position_index_bits,
+        &MerkleCapTarget(vec![root_before_target]),
+        &merkle_proof_target,
+    );
+
+    builder.verify_merkle_proof_to_cap::<PoseidonHash>(
+        [pubkey_target.elements.to_vec(), vec![zero, zero]].concat(),
+        &position_index_bits,
+        &MerkleCapTarget(vec![root_after_target]),
+        &merkle_proof_target,
+    );
+
+    builder.register_public_inputs(&root_before_target.elements);
+    builder.register_public_inputs(&root_after_target.elements);
+    builder.register_public_inputs(&pubkey_target.elements);
+
+    InsertTargets {
+        root_before_target,
+        root_after_target,
+        merkle_proof_target,
+        pubkey_target,
+        position_index_target,
+    }
+}
+
+pub fn make_insert_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
+    config: &CircuitConfig,
+    insertion: Insertion<F>
+) ->Result<ProofTuple<F,C,D>>
+    where
+    [(); C::Hasher::HASH_SIZE]:,
+{
+
+    let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+    let InsertTargets {
+        root_before_target,
+        root_after_target,
+        merkle_proof_target,
+        pubkey_target,
+        position_index_target,
+    } = insert_circuit::<F, C, D>(insertion.position_tree_depth, &mut builder);
+
+    let mut pw: PartialWitness<F> = PartialWitness::new();
+    pw.set_hash_target(root_before_target, insertion.root_before);
+    pw.set_hash_target(root_after_target, insertion.root_after);
+    pw.set_hash_target(pubkey_target, insertion.pubkey);
+    pw.set_target(position_index_target, F::from_canonical_usize(insertion.position_index));
+
+    for (ht, h) in merkle_proof_target
+    .siblings
+    .into_iter()
+    .zip(insertion.merkle_proof.siblings)
+    {
+        pw.set_hash_target(ht, h);
+    }
+    let data = builder.build::<C>();
+
+    let proof = data.prove(pw)?;
+    data.verify(proof.clone())?;
+    Ok((proof, data.verifier_only, data.common))
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::deposit::make_deposit_proof;
+    use crate::state::PositionState;
+    use crate::types::{public_key, TransactionKind, verify_proof, F, C, D};
+    use super::*;
+
+
+    #[test]
+    fn test_insert_then_deposit_at_depth_32() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let mut state = PositionState::<F>::new(32).unwrap();
+        state.insert(3, public_key([F::ONE; 4])).unwrap();
+        let insertion = state.insert((1 << 31) + 5, public_key([F::TWO; 4])).unwrap();
+        let deposit_tx = state.apply::<D>(TransactionKind::Deposit, (1 << 31) + 5, F::ONE, None).unwrap();
+
+        let (pi, vd, cd) = make_insert_proof::<F, C, D>(&standard_config, insertion).unwrap();
+        verify_proof(pi, vd, cd).unwrap();
+        let (pi, vd, cd) = make_deposit_proof::<F, C, D>(&standard_config, deposit_tx).unwrap();
+        verify_proof(pi, vd, cd).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_insert_into_occupied_position() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let mut state = PositionState::<F>::new(32).unwrap();
+        let mut insertion = state.insert(3, public_key([F::ONE; 4])).unwrap();
+        // Prove the insertion again against the tree that already holds the position.
+        insertion.root_before = insertion.root_after;
+        make_insert_proof::<F, C, D>(&standard_config, insertion).unwrap();
+    }
+
+
+}
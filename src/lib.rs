@@ -4,10 +4,27 @@ extern crate core;
 
 pub mod config;
 pub mod verifier;
+pub mod formats;
+pub mod stats;
 pub mod deposit;
 pub mod withdraw;
+pub mod transfer;
+pub mod batch;
+pub mod transaction;
+pub mod insert;
 pub mod types;
+pub mod serialization;
+pub mod witness;
+pub mod r1cs;
+pub mod security;
 pub mod recursive;
 pub mod sequencer;
+pub mod state;
+pub mod smt;
 pub mod utils;
-pub mod test;
\ No newline at end of file
+pub mod aggregation;
+pub mod estimator;
+pub mod tuning;
+pub mod test;
+#[cfg(test)]
+mod test_utils;
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{anyhow, ensure, Result};
+use num::{BigUint, Zero};
+
+use crate::witness::SymbolTable;
+
+const HEADER_SECTION: u32 = 1;
+const CONSTRAINTS_SECTION: u32 = 2;
+
+pub type LinearCombination = Vec<(usize, BigUint)>;
+
+/// `a * b = c`, over the witness wires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    pub a: LinearCombination,
+    pub b: LinearCombination,
+    pub c: LinearCombination,
+}
+
+/// A circuit read from the `.r1cs` file written by `circom --r1cs`. Only the header is read
+/// upfront: the Circom verifier has tens of millions of constraints, which take many GB in
+/// memory, so `constraints` streams them from the file instead.
+#[derive(Debug)]
+pub struct R1cs<R = BufReader<File>> {
+    pub prime: BigUint,
+    pub num_wires: usize,
+    pub num_pub_out: usize,
+    pub num_pub_in: usize,
+    pub num_prv_in: usize,
+    pub num_labels: u64,
+    pub num_constraints: usize,
+    n8: usize,
+    reader: R,
+    constraints_offset: u64,
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_field<R: Read>(reader: &mut R, n8: usize) -> Result<BigUint> {
+    let mut bytes = vec![0u8; n8];
+    reader.read_exact(&mut bytes)?;
+    Ok(BigUint::from_bytes_le(&bytes))
+}
+
+/// The offset and size of each section of an iden3 binary file, keyed by section type.
+fn section_offsets<R: Read + Seek>(
+    reader: &mut R,
+    magic: &[u8; 4],
+) -> Result<HashMap<u32, (u64, u64)>> {
+    let mut file_magic = [0u8; 4];
+    reader.read_exact(&mut file_magic)?;
+    ensure!(&file_magic == magic, "not a .{} file", String::from_utf8_lossy(magic));
+    read_u32(reader)?;
+    let num_sections = read_u32(reader)?;
+    let len = reader.seek(SeekFrom::End(0))?;
+    let mut sections = HashMap::new();
+    let mut pos = 12;
+    for _ in 0..num_sections {
+        reader.seek(SeekFrom::Start(pos))?;
+        let ty = read_u32(reader)?;
+        let size = read_u64(reader)?;
+        pos += 12;
+        ensure!(len >= pos + size, "truncated section {}", ty);
+        sections.insert(ty, (pos, size));
+        pos += size;
+    }
+    Ok(sections)
+}
+
+impl R1cs {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> R1cs<R> {
+    pub fn from_reader(mut reader: R) -> Result<Self> {
+        let sections = section_offsets(&mut reader, b"r1cs")?;
+        let (header_offset, _) = *sections
+            .get(&HEADER_SECTION)
+            .ok_or_else(|| anyhow!("the .r1cs file has no header"))?;
+        let (constraints_offset, _) = *sections
+            .get(&CONSTRAINTS_SECTION)
+            .ok_or_else(|| anyhow!("the .r1cs file has no constraints"))?;
+
+        reader.seek(SeekFrom::Start(header_offset))?;
+        let n8 = read_u32(&mut reader)? as usize;
+        let prime = read_field(&mut reader, n8)?;
+        let num_wires = read_u32(&mut reader)? as usize;
+        let num_pub_out = read_u32(&mut reader)? as usize;
+        let num_pub_in = read_u32(&mut reader)? as usize;
+        let num_prv_in = read_u32(&mut reader)? as usize;
+        let num_labels = read_u64(&mut reader)?;
+        let num_constraints = read_u32(&mut reader)? as usize;
+
+        Ok(Self {
+            prime,
+            num_wires,
+            num_pub_out,
+            num_pub_in,
+            num_prv_in,
+            num_labels,
+            num_constraints,
+            n8,
+            reader,
+            constraints_offset,
+        })
+    }
+
+    /// Reads the constraints one at a time, from the start of the constraints section.
+    pub fn constraints(&mut self) -> Result<impl Iterator<Item = Result<Constraint>> + '_> {
+        self.reader.seek(SeekFrom::Start(self.constraints_offset))?;
+        let (n8, num_wires) = (self.n8, self.num_wires);
+        let reader = &mut self.reader;
+        let mut read_lc = move || -> Result<LinearCombination> {
+            let num_terms = read_u32(reader)? as usize;
+            let mut lc = Vec::with_capacity(num_terms);
+            for _ in 0..num_terms {
+                let wire = read_u32(reader)? as usize;
+                ensure!(wire < num_wires, "wire {} out of range", wire);
+                lc.push((wire, read_field(reader, n8)?));
+            }
+            Ok(lc)
+        };
+        Ok((0..self.num_constraints).map(move |_| {
+            Ok(Constraint {
+                a: read_lc()?,
+                b: read_lc()?,
+                c: read_lc()?,
+            })
+        }))
+    }
+}
+
+/// The first constraint the witness does not satisfy, with the named signals it involves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsatisfiedConstraint {
+    pub index: usize,
+    pub signals: Vec<String>,
+}
+
+impl fmt::Display for UnsatisfiedConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "constraint {} is not satisfied", self.index)?;
+        if !self.signals.is_empty() {
+            write!(f, " by {}", self.signals.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks `witness` against every constraint of `r1cs`. Errors are for a witness that does not
+/// belong to the circuit at all; an unsatisfied constraint is reported as `Some`.
+pub fn check_witness<R: Read + Seek>(
+    r1cs: &mut R1cs<R>,
+    witness: &[BigUint],
+    sym: &SymbolTable,
+) -> Result<Option<UnsatisfiedConstraint>> {
+    ensure!(
+        witness.len() == r1cs.num_wires,
+        "the witness has {} values but the circuit has {} wires",
+        witness.len(),
+        r1cs.num_wires
+    );
+    ensure!(
+        witness.first() == Some(&BigUint::from(1u32)),
+        "the first wire of the witness must be 1"
+    );
+
+    let p = r1cs.prime.clone();
+    let eval = |lc: &LinearCombination| {
+        lc.iter()
+            .fold(BigUint::zero(), |acc, (wire, coeff)| (acc + coeff * &witness[*wire]) % &p)
+    };
+    for (index, constraint) in r1cs.constraints()?.enumerate() {
+        let constraint = constraint?;
+        if eval(&constraint.a) * eval(&constraint.b) % &p != eval(&constraint.c) {
+            // Wires are numbered in evaluation order, so the failing signal usually comes first.
+            let mut wires: Vec<usize> = [&constraint.a, &constraint.b, &constraint.c]
+                .iter()
+                .flat_map(|lc| lc.iter().map(|(wire, _)| *wire))
+                .collect();
+            wires.sort_unstable();
+            wires.dedup();
+            let signals = wires
+                .into_iter()
+                .rev()
+                .filter_map(|wire| sym.name(wire).map(str::to_string))
+                .collect();
+            return Ok(Some(UnsatisfiedConstraint { index, signals }));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::*;
+
+    fn section(ty: u32, body: &[u8]) -> Vec<u8> {
+        [&ty.to_le_bytes()[..], &(body.len() as u64).to_le_bytes()[..], body].concat()
+    }
+
+    // x * y = z and z * 1 = out, over the wires [1, out, x, y, z].
+    fn r1cs_bytes(prime: &BigUint) -> Vec<u8> {
+        let mut field = prime.to_bytes_le();
+        field.resize(32, 0);
+        let mut header = vec![];
+        header.write_all(&32u32.to_le_bytes()).unwrap();
+        header.write_all(&field).unwrap();
+        for v in [5u32, 1, 0, 2] {
+            header.write_all(&v.to_le_bytes()).unwrap();
+        }
+        header.write_all(&5u64.to_le_bytes()).unwrap();
+        header.write_all(&2u32.to_le_bytes()).unwrap();
+
+        let lc = |wire: u32| {
+            let mut one = vec![1u8];
+            one.resize(32, 0);
+            [&1u32.to_le_bytes()[..], &wire.to_le_bytes()[..], &one[..]].concat()
+        };
+        let constraints = [lc(2), lc(3), lc(4), lc(4), lc(0), lc(1)].concat();
+
+        [
+            &b"r1cs"[..],
+            &1u32.to_le_bytes()[..],
+            &2u32.to_le_bytes()[..],
+            &section(2, &constraints)[..],
+            &section(1, &header)[..],
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_check_witness() {
+        let prime = BigUint::parse_bytes(
+            b"21888242871839275222246405745257275088548364400416034343698204186575808495617",
+            10,
+        )
+        .unwrap();
+        let mut r1cs = R1cs::from_reader(Cursor::new(r1cs_bytes(&prime))).unwrap();
+        assert_eq!(r1cs.prime, prime);
+        assert_eq!(r1cs.num_wires, 5);
+        assert_eq!(r1cs.num_constraints, 2);
+
+        let sym = SymbolTable::parse("1,1,0,main.out\n2,2,0,main.x\n3,3,0,main.y\n4,4,0,main.z\n")
+            .unwrap();
+        let witness: Vec<BigUint> = [1u32, 6, 2, 3, 6].iter().map(|v| BigUint::from(*v)).collect();
+        assert_eq!(check_witness(&mut r1cs, &witness, &sym).unwrap(), None);
+
+        let mut witness = witness;
+        witness[1] = BigUint::from(7u32);
+        let unsatisfied = check_witness(&mut r1cs, &witness, &sym).unwrap().unwrap();
+        assert_eq!(unsatisfied.index, 1);
+        assert_eq!(unsatisfied.signals, vec!["main.z", "main.out"]);
+
+        assert!(check_witness(&mut r1cs, &witness[..4], &sym).is_err());
+
+        let mut truncated = r1cs_bytes(&prime);
+        truncated.truncate(truncated.len() - 1);
+        assert!(R1cs::from_reader(Cursor::new(truncated)).is_err());
+    }
+
+    #[test]
+    fn test_check_witness_circom_fixture() {
+        // `c <== a * b`, compiled by circom 2.
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/circom/test/fixtures/multiplier2");
+        let mut r1cs = R1cs::read(format!("{}/multiplier2.r1cs", fixture)).unwrap();
+        let sym = SymbolTable::read(format!("{}/multiplier2.sym", fixture)).unwrap();
+        assert_eq!((r1cs.num_wires, r1cs.num_constraints), (4, 1));
+
+        let witness = [1u32, 33, 3, 11].map(BigUint::from);
+        assert_eq!(check_witness(&mut r1cs, &witness, &sym).unwrap(), None);
+        let witness = [1u32, 34, 3, 11].map(BigUint::from);
+        let unsatisfied = check_witness(&mut r1cs, &witness, &sym).unwrap().unwrap();
+        assert_eq!(unsatisfied.signals, vec!["main.b", "main.a", "main.c"]);
+    }
+}
@@ -1,11 +1,13 @@
 use std::fmt::Write;
 
-use anyhow::Result;
-use log::Level;
+use anyhow::{ensure, Result};
+use log::{info, log_enabled, Level};
 use plonky2::field::extension::{Extendable, FieldExtension};
 use plonky2::field::types::Field;
 use plonky2::gates::noop::NoopGate;
 use plonky2::hash::hash_types::RichField;
+use plonky2::hash::merkle_tree::MerkleTree;
+use plonky2::hash::poseidon::PoseidonHash;
 use plonky2::iop::witness::{PartialWitness, WitnessWrite};
 use plonky2::plonk::circuit_builder::CircuitBuilder;
 use plonky2::plonk::circuit_data::{
@@ -15,37 +17,65 @@ use plonky2::plonk::config::GenericHashOut;
 use plonky2::plonk::config::{AlgebraicHasher, GenericConfig, Hasher};
 use plonky2::plonk::proof::ProofWithPublicInputs;
 use plonky2::plonk::prover::prove;
+use plonky2::timed;
 use plonky2::util::timing::TimingTree;
 use plonky2_util::log2_strict;
 use serde::Serialize;
 
+use crate::aggregation::{set_vd_proof_target, verifier_data_tree, verify_vd_membership};
+use crate::transaction::transaction_circuit;
+use crate::transfer::transfer_circuit;
+use crate::types::{VDProof, POSITION_TREE_DEPTH};
 
-pub fn recursive_proof<
+/// Index of the `transaction_circuit` key, which proves deposits and withdrawals, in
+/// `transaction_verifier_data_tree`.
+pub const TRANSACTION_VD_INDEX: usize = 0;
+/// Index of the `transfer_circuit` key in `transaction_verifier_data_tree`.
+pub const TRANSFER_VD_INDEX: usize = 1;
+
+/// Builds the tree of the verifier keys accepted by the rollup, i.e. the transaction circuit at
+/// `TRANSACTION_VD_INDEX` and the transfer circuit at `TRANSFER_VD_INDEX`.
+pub fn transaction_verifier_data_tree<
     F: RichField + Extendable<D>,
     C: GenericConfig<D, F = F>,
+    const D: usize,
+>(
+    config: &CircuitConfig,
+) -> Result<MerkleTree<F, PoseidonHash>>
+where
+    [(); C::Hasher::HASH_SIZE]:,
+{
+    let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+    transaction_circuit::<F, C, D>(POSITION_TREE_DEPTH, &mut builder);
+    let transaction_vd = builder.build::<C>().verifier_only;
+
+    let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+    transfer_circuit::<F, C, D>(POSITION_TREE_DEPTH, &mut builder);
+    let transfer_vd = builder.build::<C>().verifier_only;
+
+    verifier_data_tree::<F, C, D>(&[transaction_vd, transfer_vd])
+}
+
+
+/// Builds a circuit verifying the inner proof, and the witness for it.
+fn recursive_circuit<
+    F: RichField + Extendable<D>,
     InnerC: GenericConfig<D, F = F>,
     const D: usize,
 >(
-    inner_proof: ProofWithPublicInputs<F, InnerC, D>,
-    inner_vd: VerifierOnlyCircuitData<InnerC, D>,
-    inner_cd: CommonCircuitData<F, D>,
+    inner_proof: &ProofWithPublicInputs<F, InnerC, D>,
+    inner_vd: &VerifierOnlyCircuitData<InnerC, D>,
+    inner_cd: &CommonCircuitData<F, D>,
+    vd_proof: Option<&VDProof<F>>,
     config: &CircuitConfig,
-    min_degree_bits: Option<usize>,
-    print_gate_counts: bool,
-    print_timing: bool,
-) -> Result<(
-    ProofWithPublicInputs<F, C, D>,
-    VerifierOnlyCircuitData<C, D>,
-    CommonCircuitData<F, D>,
-)>
+) -> (CircuitBuilder<F, D>, PartialWitness<F>)
 where
     InnerC::Hasher: AlgebraicHasher<F>,
-    [(); C::Hasher::HASH_SIZE]:,
 {
     let mut builder = CircuitBuilder::<F, D>::new(config.clone());
     let mut pw = PartialWitness::new();
-    let pt = builder.add_virtual_proof_with_pis::<InnerC>(&inner_cd);
-    pw.set_proof_with_pis_target(&pt, &inner_proof);
+    let pt = builder.add_virtual_proof_with_pis::<InnerC>(inner_cd);
+    pw.set_proof_with_pis_target(&pt, inner_proof);
 
     let inner_data = VerifierCircuitTarget {
         constants_sigmas_cap: builder.add_virtual_cap(inner_cd.config.fri_config.cap_height),
@@ -61,32 +91,186 @@ where
     for i in 0..builder.config.fri_config.num_cap_elements() {
         builder.register_public_inputs(&inner_data.constants_sigmas_cap.0[i].elements);
     }
-    builder.verify_proof::<InnerC>(&pt, &inner_data, &inner_cd);
+    // With a `VDProof` the inner verifier key must be one of the allowlisted ones, and the root of
+    // the allowlist is exposed after the inner verifier key.
+    if let Some(vd_proof) = vd_proof {
+        let root_target = builder.add_virtual_hash();
+        pw.set_hash_target(root_target, vd_proof.root);
+        builder.register_public_inputs(&root_target.elements);
+        let vd_proof_target = verify_vd_membership(
+            &mut builder,
+            &inner_data,
+            root_target,
+            vd_proof.merkle_proof.siblings.len(),
+        );
+        set_vd_proof_target(&mut pw, &vd_proof_target, vd_proof);
+    }
+    builder.verify_proof::<InnerC>(&pt, &inner_data, inner_cd);
+
+    (builder, pw)
+}
+
+fn pad_to_degree_bits<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    min_degree_bits: usize,
+) {
+    // We don't want to pad all the way up to 2^min_degree_bits, as the builder will add a
+    // few special gates afterward. So just pad to 2^(min_degree_bits - 1) + 1. Then the
+    // builder will pad to the next power of two, 2^min_degree_bits.
+    let min_gates = (1 << (min_degree_bits - 1)) + 1;
+    for _ in builder.num_gates()..min_gates {
+        builder.add_gate(NoopGate, vec![]);
+    }
+}
 
-    if print_gate_counts {
+pub fn recursive_proof<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    InnerC: GenericConfig<D, F = F>,
+    const D: usize,
+>(
+    inner_proof: ProofWithPublicInputs<F, InnerC, D>,
+    inner_vd: VerifierOnlyCircuitData<InnerC, D>,
+    inner_cd: CommonCircuitData<F, D>,
+    vd_proof: Option<VDProof<F>>,
+    config: &CircuitConfig,
+    min_degree_bits: Option<usize>,
+) -> Result<(
+    ProofWithPublicInputs<F, C, D>,
+    VerifierOnlyCircuitData<C, D>,
+    CommonCircuitData<F, D>,
+)>
+where
+    InnerC::Hasher: AlgebraicHasher<F>,
+    [(); C::Hasher::HASH_SIZE]:,
+{
+    let (mut builder, pw) = recursive_circuit(
+        &inner_proof,
+        &inner_vd,
+        &inner_cd,
+        vd_proof.as_ref(),
+        config,
+    );
+
+    // Gate counts and the timing tree are only printed when debug logging is enabled.
+    if log_enabled!(Level::Debug) {
         builder.print_gate_counts(0);
     }
 
     if let Some(min_degree_bits) = min_degree_bits {
-        // We don't want to pad all the way up to 2^min_degree_bits, as the builder will add a
-        // few special gates afterward. So just pad to 2^(min_degree_bits - 1) + 1. Then the
-        // builder will pad to the next power of two, 2^min_degree_bits.
-        let min_gates = (1 << (min_degree_bits - 1)) + 1;
-        for _ in builder.num_gates()..min_gates {
-            builder.add_gate(NoopGate, vec![]);
-        }
+        pad_to_degree_bits(&mut builder, min_degree_bits);
     }
 
-    let data = builder.build::<C>();
-
-    let mut timing = TimingTree::new("prove", Level::Debug);
-    let proof = prove(&data.prover_only, &data.common, pw, &mut timing)?;
-    if print_timing {
+    let mut timing = TimingTree::new("recursive proof", Level::Debug);
+    let data = timed!(timing, Level::Debug, "build circuit", builder.build::<C>());
+    info!("recursive circuit has 2^{} gates", data.common.degree_bits());
+    let proof = timed!(
+        timing,
+        Level::Debug,
+        "prove",
+        prove(&data.prover_only, &data.common, pw, &mut timing)?
+    );
+    timed!(timing, Level::Debug, "verify", data.verify(proof.clone())?);
+    if log_enabled!(Level::Debug) {
         timing.print();
     }
 
-    println!("######################### recursive verify #########################");
-    data.verify(proof.clone())?;
+    Ok((proof, data.verifier_only, data.common))
+}
+
+/// Fixes the shape of a standardized final layer: the `CommonCircuitData` of a wrapper of the
+/// given inner circuit, padded to `degree_bits`. Inner circuits wrapped later with
+/// `final_layer_proof` against this shape produce the same common data, but their verifier keys
+/// still differ. Wrapping the final layer proofs once more with `recursive_proof` and a
+/// `VDProof` against the tree of their keys gives a single outer verifier key, so the Circom
+/// verifier and its Groth16 setup generated for it stay valid across inner circuit upgrades.
+pub fn final_layer_shape<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    InnerC: GenericConfig<D, F = F>,
+    const D: usize,
+>(
+    inner_proof: &ProofWithPublicInputs<F, InnerC, D>,
+    inner_vd: &VerifierOnlyCircuitData<InnerC, D>,
+    inner_cd: &CommonCircuitData<F, D>,
+    vd_proof: Option<&VDProof<F>>,
+    config: &CircuitConfig,
+    degree_bits: usize,
+) -> Result<CommonCircuitData<F, D>>
+where
+    InnerC::Hasher: AlgebraicHasher<F>,
+    [(); C::Hasher::HASH_SIZE]:,
+{
+    let (mut builder, _) = recursive_circuit(inner_proof, inner_vd, inner_cd, vd_proof, config);
+    pad_to_degree_bits(&mut builder, degree_bits);
+    let common = builder.build::<C>().common;
+    ensure!(
+        common.degree_bits() == degree_bits,
+        "the final layer needs 2^{} gates, more than the requested 2^{}",
+        common.degree_bits(),
+        degree_bits
+    );
+    Ok(common)
+}
+
+/// Wraps the inner proof in a circuit with exactly the common data of `shape`, or fails if the
+/// inner circuit does not fit into it. The returned verifier key depends on the inner circuit.
+pub fn final_layer_proof<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    InnerC: GenericConfig<D, F = F>,
+    const D: usize,
+>(
+    inner_proof: ProofWithPublicInputs<F, InnerC, D>,
+    inner_vd: VerifierOnlyCircuitData<InnerC, D>,
+    inner_cd: CommonCircuitData<F, D>,
+    vd_proof: Option<VDProof<F>>,
+    shape: &CommonCircuitData<F, D>,
+) -> Result<(
+    ProofWithPublicInputs<F, C, D>,
+    VerifierOnlyCircuitData<C, D>,
+    CommonCircuitData<F, D>,
+)>
+where
+    InnerC::Hasher: AlgebraicHasher<F>,
+    [(); C::Hasher::HASH_SIZE]:,
+{
+    let (mut builder, pw) = recursive_circuit(
+        &inner_proof,
+        &inner_vd,
+        &inner_cd,
+        vd_proof.as_ref(),
+        &shape.config,
+    );
+    // The same gate set gives the same selectors and constraint layout.
+    for gate in shape.gates.iter() {
+        builder.add_gate_to_gate_set(gate.clone());
+    }
+    pad_to_degree_bits(&mut builder, shape.degree_bits());
+
+    let mut timing = TimingTree::new("final layer proof", Level::Debug);
+    let data = timed!(timing, Level::Debug, "build circuit", builder.build::<C>());
+    ensure!(
+        data.common.degree_bits() == shape.degree_bits(),
+        "inner circuit does not fit the final layer: needs 2^{} gates, the final layer has 2^{}",
+        data.common.degree_bits(),
+        shape.degree_bits()
+    );
+    ensure!(
+        data.common == *shape,
+        "inner circuit changes the common data of the final layer"
+    );
+
+    let proof = timed!(
+        timing,
+        Level::Debug,
+        "prove",
+        prove(&data.prover_only, &data.common, pw, &mut timing)?
+    );
+    timed!(timing, Level::Debug, "verify", data.verify(proof.clone())?);
+    if log_enabled!(Level::Debug) {
+        timing.print();
+    }
 
     Ok((proof, data.verifier_only, data.common))
-}
\ No newline at end of file
+}
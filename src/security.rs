@@ -0,0 +1,185 @@
+use anyhow::{ensure, Result};
+use plonky2::field::extension::Extendable;
+use plonky2::fri::FriConfig;
+use plonky2::hash::hash_types::RichField;
+use plonky2::plonk::circuit_data::CommonCircuitData;
+use plonky2_util::log2_strict;
+use serde::{Deserialize, Serialize};
+
+use crate::verifier::VerifierConfig;
+
+/// What the soundness of a proof depends on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityParams {
+    pub field_bits: usize,
+    pub extension_field_bits: usize,
+    pub degree_bits: usize,
+    pub rate_bits: usize,
+    pub num_query_rounds: usize,
+    pub proof_of_work_bits: u32,
+    pub num_challenges: usize,
+    pub num_routed_wires: usize,
+    pub quotient_degree_factor: usize,
+}
+
+/// Soundness of a proof in bits, for each place a cheating prover can get lucky. Conjectured
+/// bits use the ethSTARK conjecture for FRI, proven bits the Johnson bound.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SecurityLevel {
+    pub params: SecurityParams,
+    /// FRI queries plus grinding.
+    pub conjectured_fri_bits: f64,
+    pub proven_fri_bits: f64,
+    /// The permutation and combination challenges, sampled `num_challenges` times in the base
+    /// field.
+    pub challenge_bits: f64,
+    /// The out-of-domain point `zeta`, sampled in the extension field.
+    pub zeta_bits: f64,
+    /// The FRI folding challenges, sampled in the extension field.
+    pub conjectured_folding_bits: f64,
+    pub proven_folding_bits: f64,
+}
+
+/// Conjectured FRI soundness of `config`, i.e. `rate_bits` per query plus the grinding bits.
+pub fn conjectured_fri_bits(config: &FriConfig) -> usize {
+    fri_bits(config.rate_bits, config.num_query_rounds, config.proof_of_work_bits)
+}
+
+fn fri_bits(rate_bits: usize, num_query_rounds: usize, proof_of_work_bits: u32) -> usize {
+    rate_bits * num_query_rounds + proof_of_work_bits as usize
+}
+
+fn log2(n: usize) -> f64 {
+    (n as f64).log2()
+}
+
+impl SecurityLevel {
+    pub fn new(params: SecurityParams) -> Self {
+        let p = &params;
+        let grinding_bits = p.proof_of_work_bits as f64;
+        let lde_bits = (p.degree_bits + p.rate_bits) as f64;
+        // Schwartz-Zippel over the permutation argument, whose polynomials have degree
+        // `num_routed_wires << degree_bits`.
+        let per_challenge = p.field_bits as f64 - p.degree_bits as f64 - log2(p.num_routed_wires);
+        Self {
+            params,
+            conjectured_fri_bits: fri_bits(p.rate_bits, p.num_query_rounds, p.proof_of_work_bits)
+                as f64,
+            // Within the Johnson bound a query only rules out a `sqrt(rate)` fraction.
+            proven_fri_bits: (p.rate_bits * p.num_query_rounds) as f64 / 2.0 + grinding_bits,
+            challenge_bits: p.num_challenges as f64 * per_challenge,
+            zeta_bits: p.extension_field_bits as f64
+                - p.degree_bits as f64
+                - log2(p.quotient_degree_factor),
+            conjectured_folding_bits: p.extension_field_bits as f64 - lde_bits,
+            // The proximity gaps bound grows with the square of the LDE domain.
+            proven_folding_bits: p.extension_field_bits as f64 - 2.0 * lde_bits,
+        }
+    }
+
+    pub fn from_common<F: RichField + Extendable<D>, const D: usize>(
+        common: &CommonCircuitData<F, D>,
+    ) -> Self {
+        let config = &common.config;
+        Self::new(SecurityParams {
+            field_bits: F::BITS,
+            extension_field_bits: F::Extension::BITS,
+            degree_bits: common.degree_bits(),
+            rate_bits: config.fri_config.rate_bits,
+            num_query_rounds: config.fri_config.num_query_rounds,
+            proof_of_work_bits: config.fri_config.proof_of_work_bits,
+            num_challenges: config.num_challenges,
+            num_routed_wires: config.num_routed_wires,
+            quotient_degree_factor: common.quotient_degree_factor,
+        })
+    }
+
+    /// `VerifierConfig` does not record the grinding, so `proof_of_work_bits` is passed along.
+    pub fn from_verifier_config(conf: &VerifierConfig, proof_of_work_bits: u32) -> Result<Self> {
+        ensure!(conf.num_openings_plonk_zs > 0, "the config has no challenges");
+        let num_challenges = conf.num_openings_plonk_zs;
+        // The degree is split between the folding steps and the final polynomial, and the config
+        // only records the sizes of two steps.
+        ensure!(
+            conf.num_fri_commit_round == 2,
+            "expected 2 FRI folding steps, the config has {}",
+            conf.num_fri_commit_round
+        );
+        let degree_bits = log2_strict(conf.num_fri_final_poly_ext_v)
+            + log2_strict(conf.num_fri_query_step0_v)
+            + log2_strict(conf.num_fri_query_step1_v);
+        // The initial Merkle proofs go from the LDE leaves to the cap.
+        let lde_bits =
+            conf.num_fri_query_init_wires_p + log2_strict(conf.fri_commit_merkle_cap_height);
+        ensure!(lde_bits > degree_bits, "the config has no LDE blowup");
+
+        Ok(Self::new(SecurityParams {
+            field_bits: conf.field_size * 8,
+            extension_field_bits: conf.ext_field_size * 8,
+            degree_bits,
+            rate_bits: lde_bits - degree_bits,
+            num_query_rounds: conf.num_fri_query_round,
+            proof_of_work_bits,
+            num_challenges,
+            num_routed_wires: conf.num_openings_plonk_sigmas,
+            quotient_degree_factor: conf.num_openings_quotient_polys / num_challenges,
+        }))
+    }
+
+    pub fn conjectured_bits(&self) -> f64 {
+        self.conjectured_fri_bits
+            .min(self.challenge_bits)
+            .min(self.zeta_bits)
+            .min(self.conjectured_folding_bits)
+    }
+
+    pub fn proven_bits(&self) -> f64 {
+        self.proven_fri_bits
+            .min(self.challenge_bits)
+            .min(self.zeta_bits)
+            .min(self.proven_folding_bits)
+    }
+
+    /// Errors if the conjectured security is below `min_bits`.
+    pub fn ensure_at_least(&self, min_bits: usize) -> Result<()> {
+        ensure!(
+            self.conjectured_bits() >= min_bits as f64,
+            "the circuit has {:.1} bits of conjectured security, below the required {}",
+            self.conjectured_bits(),
+            min_bits
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::test_utils::{dummy_proof, final_config};
+    use crate::types::{Cbn128, D, F};
+    use crate::verifier::verifier_config_from_common;
+
+    use super::*;
+
+    #[test]
+    fn test_security_level() -> Result<()> {
+        let (_, _, common) = dummy_proof::<F, Cbn128, D>(&final_config(), 4_000, 1)?;
+
+        let level = SecurityLevel::from_common(&common);
+        assert_eq!(level.conjectured_fri_bits, 100.0);
+        assert_eq!(level.proven_fri_bits, 60.0);
+        assert!(level.proven_bits() <= level.conjectured_bits());
+        assert!(level.conjectured_bits() <= 100.0);
+        // Two base field challenges over a 2^12 domain give less than the FRI part.
+        assert!(level.challenge_bits < level.conjectured_fri_bits);
+        assert!(level.ensure_at_least(101).is_err());
+        assert!(level.ensure_at_least(level.conjectured_bits() as usize).is_ok());
+
+        let conf = verifier_config_from_common(&common)?;
+        assert_eq!(SecurityLevel::from_verifier_config(&conf, 20)?, level);
+        let three_steps = VerifierConfig { num_fri_commit_round: 3, ..conf };
+        assert!(SecurityLevel::from_verifier_config(&three_steps, 20).is_err());
+        Ok(())
+    }
+}
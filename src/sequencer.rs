@@ -1,87 +1,75 @@
 use plonky2::field::extension::Extendable;
 use plonky2::field::types::Field;
 use plonky2::hash::hash_types::RichField;
-use plonky2::hash::merkle_tree::MerkleTree;
 use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::hash::hash_types::HashOut;
+use plonky2::hash::merkle_proofs::MerkleProof;
+use plonky2::plonk::config::Hasher;
 use rand::Rng;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use plonky2::plonk::circuit_data::CircuitConfig;
+use plonky2::plonk::config::GenericConfig;
+use plonky2::plonk::proof::ProofWithPublicInputs;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
 
-use crate::types::{Transaction, ILLEGAL_DEPOSIT};
+use crate::state::PositionState;
+use crate::types::{public_key, TransactionKind, Transaction, Position};
 
-use crate::types::{VDProof, DEPOSIT, WITHDRAW, RECURSIVE0, RECURSIVE1, POSITION_TREE_DEPTH};
+use crate::types::{VDProof, POSITION_TREE_DEPTH};
 
-use crate::types::{F, C, D, verify_proof};
+use crate::types::{F, C, D, verify_proof, ProofTuple, BALANCE_BITS};
+use crate::deposit::make_deposit_proof;
+use crate::withdraw::check_withdraw_proof;
+use crate::transfer::check_transfer_proof;
 
 
-pub fn generate_transactions<F: RichField + Extendable<D>, const D: usize>(transaction_sequence: Vec<usize>) -> Result<Vec<Transaction<F, D>>> {
-    let mut transactions = Vec::new();
+/// Recomputes the root of a position tree from a leaf and its Merkle proof.
+pub fn root_from_merkle_proof<F: RichField>(
+    leaf_data: Vec<F>,
+    leaf_index: usize,
+    merkle_proof: &MerkleProof<F, PoseidonHash>,
+) -> HashOut<F> {
+    let mut index = leaf_index;
+    let mut current = PoseidonHash::hash_or_noop(&leaf_data);
+    for &sibling in merkle_proof.siblings.iter() {
+        current = if index & 1 == 0 {
+            PoseidonHash::two_to_one(current, sibling)
+        } else {
+            PoseidonHash::two_to_one(sibling, current)
+        };
+        index >>= 1;
+    }
+    current
+}
 
-    let n:u64 = 1 << POSITION_TREE_DEPTH;
-    // let private_keys: Vec<Digest> = (0..n).map(|_| F::rand_arr()).collect();
+pub fn generate_transactions<F: RichField + Extendable<D>, const D: usize>(transaction_sequence: Vec<TransactionKind>) -> Result<Vec<Transaction<F, D>>> {
+    let mut transactions = Vec::new();
 
-    let mut positions = vec![vec![F::from_canonical_u64(100)]; n as usize];
-    let mut positions_tree = MerkleTree::<F, PoseidonHash>::new(positions.clone(), 0);
+    // The owners' keys, which the state does not keep.
+    let private_keys: Vec<[F; 4]> = (0..1 << POSITION_TREE_DEPTH).map(|_| F::rand_arr()).collect();
+    let mut state = PositionState::new(POSITION_TREE_DEPTH)?;
+    for (index, &private_key) in private_keys.iter().enumerate() {
+        state.open_position(index, public_key(private_key), F::from_canonical_u64(100))?;
+    }
     let amount = F::ONE;
     let position_index: usize = 10;
+    let to_position_index: usize = 11;
     for i in transaction_sequence {
-        let balance = positions_tree.get(position_index)[0];
-        let r0 = positions_tree.cap.0[0];
-        let mk_proof = positions_tree.prove(position_index);
-
-        // 0: deposit， 1: withdraw
         match i {
-            DEPOSIT => {
-                positions[position_index][0] += amount;
-                positions_tree = MerkleTree::<F, PoseidonHash>::new(positions.clone(), 0);
-                let r1 = positions_tree.cap.0[0];
-                let deposit_tx = Transaction{
-                    transaction_type: DEPOSIT,
-                    root_before: r0,
-                    root_after: r1,
-                    merkle_proof: mk_proof,
-                    balance_before: balance,
-                    position_index: position_index,
-                    amount: amount,
-                    position_tree_depth: POSITION_TREE_DEPTH,
-                    // vd_deposit_proof: vd_proofs[DEPOSIT].clone(),
-                };
-                transactions.push(deposit_tx);
+            TransactionKind::Deposit => {
+                transactions.push(state.apply(i, position_index, amount, None)?);
+            },
+            TransactionKind::Withdraw => {
+                let tx = state.apply(i, position_index, amount, None)?;
+                transactions.push(Transaction { private_key: Some(private_keys[position_index]), ..tx });
             },
-            WITHDRAW => {
-                positions[position_index][0] -= amount;
-                positions_tree = MerkleTree::<F, PoseidonHash>::new(positions.clone(), 0);
-                let r1 = positions_tree.cap.0[0];
-                let withdraw_tx = Transaction{
-                    transaction_type: WITHDRAW,
-                    root_before: r0,
-                    root_after: r1,
-                    merkle_proof: mk_proof,
-                    balance_before: balance,
-                    position_index: position_index,
-                    amount: amount,
-                    position_tree_depth: POSITION_TREE_DEPTH,
-                    // vd_deposit_proof: vd_proofs[WITHDRAW].clone(),
-                };
-                transactions.push(withdraw_tx);
+            TransactionKind::Transfer => {
+                let tx = state.apply(i, position_index, amount, Some(to_position_index))?;
+                transactions.push(Transaction { private_key: Some(private_keys[position_index]), ..tx });
             },
-            // ILLEGAL_DEPOSIT => {
-            //     positions[position_index][0] *= amount;
-            //     positions_tree = PositionSet(MerkleTree::new(positions.clone(), 0));
-            //     let r1 = positions_tree.0.cap.0[0];
-            //     let illegal_deposit_tx = Transaction{
-            //         transaction_type: ILLEGAL_DEPOSIT,
-            //         root_before: r0,
-            //         root_after: r1,
-            //         merkle_proof: mk_proof,
-            //         balance_before: balance,
-            //         position_index: position_index,
-            //         amount: amount,
-            //         position_tree_depth: POSITION_TREE_DEPTH,
-            //         vd_deposit_proof: vd_proofs[DEPOSIT].clone(),
-            //     };
-            //     transactions.push(illegal_deposit_tx);
-            // },
-            _ => panic!("invalid transaction type"),
+            _ => return Err(anyhow!("invalid transaction type {:?}", i)),
         }
 
     };
@@ -91,9 +79,210 @@ pub fn generate_transactions<F: RichField + Extendable<D>, const D: usize>(trans
 
 }
 
+/// A transaction submitted to the sequencer. `nonce` is the position's nonce the owner proved
+/// against, and is ignored for deposits.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(bound = "")]
+pub struct TxRequest<F: RichField> {
+    pub transaction_type: TransactionKind,
+    pub position_index: usize,
+    pub amount: F,
+    pub nonce: F,
+    pub to_position_index: Option<usize>,
+}
+
+pub struct Receipt<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize> {
+    pub transaction: Transaction<F, D>,
+    pub proof: ProofTuple<F, C, D>,
+}
+
+#[derive(Debug)]
+pub enum TxError {
+    UnsupportedTransactionKind(TransactionKind),
+    IndexOutOfRange(usize),
+    PositionNotOpen(usize),
+    AmountOutOfRange,
+    MissingReceiver,
+    SelfTransfer,
+    MissingAuthorization,
+    Unauthorized(anyhow::Error),
+    NonceMismatch { expected: u64, got: u64 },
+    InsufficientBalance { balance: u64, amount: u64 },
+    BalanceOverflow(usize),
+    State(anyhow::Error),
+    Prover(anyhow::Error),
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxError::UnsupportedTransactionKind(t) => write!(f, "unsupported transaction kind {:?}", t),
+            TxError::IndexOutOfRange(i) => write!(f, "position {} out of range", i),
+            TxError::PositionNotOpen(i) => write!(f, "position {} is not open", i),
+            TxError::AmountOutOfRange => write!(f, "amount out of range"),
+            TxError::MissingReceiver => write!(f, "transfer without a receiver"),
+            TxError::SelfTransfer => write!(f, "transfer to the sending position"),
+            TxError::MissingAuthorization => write!(f, "missing the owner's proof"),
+            TxError::Unauthorized(e) => write!(f, "invalid owner's proof: {}", e),
+            TxError::NonceMismatch { expected, got } => {
+                write!(f, "nonce {} does not match the position's nonce {}", got, expected)
+            }
+            TxError::InsufficientBalance { balance, amount } => {
+                write!(f, "amount {} exceeds the balance {}", amount, balance)
+            }
+            TxError::BalanceOverflow(i) => write!(f, "balance of position {} would overflow", i),
+            TxError::State(e) => write!(f, "rejected by the position state: {}", e),
+            TxError::Prover(e) => write!(f, "proving failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TxError {}
+
+/// Validates transactions against a `PositionState`, applies them and proves them.
+pub struct Sequencer<F: RichField> {
+    pub state: PositionState<F>,
+    pub config: CircuitConfig,
+}
+
+impl<F: RichField> Sequencer<F> {
+    pub fn new(state: PositionState<F>, config: CircuitConfig) -> Self {
+        Self { state, config }
+    }
+
+    fn check_index(&self, index: usize) -> Result<(), TxError> {
+        if index >> self.state.depth() != 0 {
+            return Err(TxError::IndexOutOfRange(index));
+        }
+        Ok(())
+    }
+
+    fn check_credit(&self, index: usize, amount: u64) -> Result<(), TxError> {
+        if !self.state.is_open(index) {
+            return Err(TxError::PositionNotOpen(index));
+        }
+        if self.state.position(index).balance.to_canonical_u64() + amount >= 1 << BALANCE_BITS {
+            return Err(TxError::BalanceOverflow(index));
+        }
+        Ok(())
+    }
+
+    /// Checks `tx` against the current state without applying it.
+    pub fn validate(&self, tx: &TxRequest<F>) -> Result<(), TxError> {
+        self.check_index(tx.position_index)?;
+        let amount = tx.amount.to_canonical_u64();
+        if amount >= 1 << BALANCE_BITS {
+            return Err(TxError::AmountOutOfRange);
+        }
+        let position = self.state.position(tx.position_index);
+
+        match tx.transaction_type {
+            TransactionKind::Deposit => self.check_credit(tx.position_index, amount),
+            TransactionKind::Withdraw | TransactionKind::Transfer => {
+                if tx.transaction_type == TransactionKind::Transfer {
+                    let to_index = tx.to_position_index.ok_or(TxError::MissingReceiver)?;
+                    self.check_index(to_index)?;
+                    if to_index == tx.position_index {
+                        return Err(TxError::SelfTransfer);
+                    }
+                    self.check_credit(to_index, amount)?;
+                }
+                if tx.nonce != position.nonce {
+                    return Err(TxError::NonceMismatch {
+                        expected: position.nonce.to_canonical_u64(),
+                        got: tx.nonce.to_canonical_u64(),
+                    });
+                }
+                let balance = position.balance.to_canonical_u64();
+                if amount > balance {
+                    return Err(TxError::InsufficientBalance { balance, amount });
+                }
+                Ok(())
+            }
+            t => Err(TxError::UnsupportedTransactionKind(t)),
+        }
+    }
+
+    /// Validates `tx` and returns the witness its owner proves against, without applying it.
+    /// The owner attaches their private key, proves it with `make_withdraw_proof` or
+    /// `make_transfer_proof` and passes the proof to `submit`.
+    pub fn prepare<const D: usize>(
+        &mut self,
+        tx: &TxRequest<F>,
+    ) -> Result<Transaction<F, D>, TxError>
+    where
+        F: Extendable<D>,
+    {
+        self.validate(tx)?;
+        let transaction = self
+            .state
+            .preview::<D>(tx.transaction_type, tx.position_index, tx.amount, tx.to_position_index)
+            .map_err(TxError::State)?;
+        Ok(Transaction { private_key: None, ..transaction })
+    }
+
+    /// Validates `tx`, applies it and proves it. Withdrawals and transfers are proven by the
+    /// owner, whose proof `authorization` is checked against the applied transaction instead.
+    /// The state is left untouched if `tx` is rejected or cannot be proven.
+    pub fn submit<C: GenericConfig<D, F = F>, const D: usize>(
+        &mut self,
+        tx: TxRequest<F>,
+        authorization: Option<ProofWithPublicInputs<F, C, D>>,
+    ) -> Result<Receipt<F, C, D>, TxError>
+    where
+        F: Extendable<D>,
+        [(); C::Hasher::HASH_SIZE]:,
+    {
+        self.validate(&tx)?;
+        let authorization = match tx.transaction_type {
+            TransactionKind::Deposit => None,
+            _ => Some(authorization.ok_or(TxError::MissingAuthorization)?),
+        };
+        let transaction = self
+            .state
+            .apply::<D>(tx.transaction_type, tx.position_index, tx.amount, tx.to_position_index)
+            .map_err(TxError::State)?;
+
+        let proof = match authorization {
+            None => catch_prover_panic(|| {
+                make_deposit_proof::<F, C, D>(&self.config, transaction.clone())
+            })
+            .map_err(TxError::Prover),
+            Some(proof) if tx.transaction_type == TransactionKind::Withdraw => {
+                check_withdraw_proof::<F, C, D>(&self.config, &transaction, proof)
+                    .map_err(TxError::Unauthorized)
+            }
+            Some(proof) => check_transfer_proof::<F, C, D>(&self.config, &transaction, proof)
+                .map_err(TxError::Unauthorized),
+        };
+        match proof {
+            Ok(proof) => Ok(Receipt { transaction, proof }),
+            Err(e) => {
+                self.state.revert(&transaction);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Runs the prover, turning a panic into an error. plonky2 panics rather than failing when the
+/// witness does not satisfy the circuit.
+fn catch_prover_panic<T>(prove: impl FnOnce() -> Result<T>) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(prove)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| payload.downcast_ref::<&str>().copied())
+            .unwrap_or("unknown panic");
+        Err(anyhow!("the prover panicked: {}", message))
+    })
+}
+
 #[cfg(test)]
 mod tests{
-    use plonky2::hash::{merkle_proofs::MerkleProof, hash_types::HashOut};
+    use crate::transfer::make_transfer_proof;
+    use crate::withdraw::make_withdraw_proof;
+
     use super::*;
 
     #[test]
@@ -104,8 +293,143 @@ mod tests{
             root: HashOut::default(),
         };
         // let vd_proofs = vec![vd_proof; 4];
-        let sequency = vec![0, 0, 1, 1];
+        let sequency = vec![TransactionKind::Deposit, TransactionKind::Deposit, TransactionKind::Withdraw, TransactionKind::Withdraw];
         let txs = generate_transactions::<F,D>(sequency).unwrap();
         assert_eq!(txs.len(), 4);
     }
+
+    #[test]
+    fn test_root_from_merkle_proof() {
+        let txs = generate_transactions::<F,D>(vec![TransactionKind::Deposit]).unwrap();
+        let tx = &txs[0];
+        let mut position = Position { pubkey: tx.pubkey, balance: tx.balance_before, nonce: tx.nonce };
+        let root_before = root_from_merkle_proof(position.to_leaf(), tx.position_index, &tx.merkle_proof);
+        assert_eq!(root_before, tx.root_before);
+        position.balance += tx.amount;
+        let root_after = root_from_merkle_proof(position.to_leaf(), tx.position_index, &tx.merkle_proof);
+        assert_eq!(root_after, tx.root_after);
+    }
+
+    fn sequencer() -> Sequencer<F> {
+        let mut state = PositionState::new(POSITION_TREE_DEPTH).unwrap();
+        state.open_position(10, public_key([F::ONE; 4]), F::from_canonical_u64(100)).unwrap();
+        state.open_position(11, public_key([F::TWO; 4]), F::from_canonical_u64((1 << BALANCE_BITS) - 1)).unwrap();
+        state.open_position(12, public_key([F::from_canonical_u64(3); 4]), F::ZERO).unwrap();
+        Sequencer::new(state, CircuitConfig::standard_recursion_config())
+    }
+
+    fn request(transaction_type: TransactionKind, amount: u64) -> TxRequest<F> {
+        TxRequest {
+            transaction_type,
+            position_index: 10,
+            amount: F::from_canonical_u64(amount),
+            nonce: F::ZERO,
+            to_position_index: None,
+        }
+    }
+
+    /// Proves `tx` as the owner of position 10 would.
+    fn authorize(sequencer: &mut Sequencer<F>, tx: &TxRequest<F>) -> ProofWithPublicInputs<F, C, D> {
+        let witness = sequencer.prepare::<D>(tx).unwrap();
+        let signed = Transaction { private_key: Some([F::ONE; 4]), ..witness };
+        let proof = match tx.transaction_type {
+            TransactionKind::Withdraw => make_withdraw_proof::<F, C, D>(&sequencer.config, signed),
+            _ => make_transfer_proof::<F, C, D>(&sequencer.config, signed),
+        };
+        proof.unwrap().0
+    }
+
+    #[test]
+    fn test_submit() {
+        let mut sequencer = sequencer();
+        let tx = request(TransactionKind::Withdraw, 100);
+        let authorization = authorize(&mut sequencer, &tx);
+        let receipt = sequencer.submit::<C, D>(tx, Some(authorization)).unwrap();
+        assert_eq!(receipt.transaction.root_after, sequencer.state.root());
+        verify_proof(receipt.proof.0, receipt.proof.1, receipt.proof.2).unwrap();
+        assert_eq!(sequencer.state.position(10).nonce, F::ONE);
+
+        let receipt = sequencer.submit::<C, D>(request(TransactionKind::Deposit, 5), None).unwrap();
+        assert_eq!(receipt.transaction.root_after, sequencer.state.root());
+    }
+
+    #[test]
+    fn test_submit_rejects_mismatched_authorization() {
+        let mut sequencer = sequencer();
+        let root = sequencer.state.root();
+
+        // Proven for another amount.
+        let authorization = authorize(&mut sequencer, &request(TransactionKind::Withdraw, 50));
+        let err = sequencer.submit::<C, D>(request(TransactionKind::Withdraw, 40), Some(authorization)).err().unwrap();
+        assert!(matches!(err, TxError::Unauthorized(_)));
+
+        // A withdrawal proof does not authorize a transfer.
+        let authorization = authorize(&mut sequencer, &request(TransactionKind::Withdraw, 1));
+        let transfer = TxRequest { to_position_index: Some(12), ..request(TransactionKind::Transfer, 1) };
+        let err = sequencer.submit::<C, D>(transfer, Some(authorization)).err().unwrap();
+        assert!(matches!(err, TxError::Unauthorized(_)));
+
+        assert_eq!(sequencer.state.root(), root);
+    }
+
+    #[test]
+    fn test_submit_reverts_on_prover_panic() {
+        let mut sequencer = sequencer();
+        let root = sequencer.state.root();
+        // Too few wires for the Poseidon gate, so building the deposit circuit panics.
+        sequencer.config = CircuitConfig { num_wires: 20, num_routed_wires: 20, ..sequencer.config.clone() };
+        let err = sequencer.submit::<C, D>(request(TransactionKind::Deposit, 1), None).err().unwrap();
+        assert!(matches!(err, TxError::Prover(_)));
+        assert_eq!(sequencer.state.root(), root);
+    }
+
+    #[test]
+    fn test_submit_rejections() {
+        let mut sequencer = sequencer();
+        let root = sequencer.state.root();
+
+        let mut reject = |tx: TxRequest<F>| sequencer.submit::<C, D>(tx, None).err().unwrap();
+        assert!(matches!(reject(request(TransactionKind::IllegalDeposit, 1)), TxError::UnsupportedTransactionKind(TransactionKind::IllegalDeposit)));
+        assert!(matches!(
+            reject(TxRequest { position_index: 1 << POSITION_TREE_DEPTH, ..request(TransactionKind::Deposit, 1) }),
+            TxError::IndexOutOfRange(_)
+        ));
+        assert!(matches!(reject(request(TransactionKind::Deposit, 1 << BALANCE_BITS)), TxError::AmountOutOfRange));
+        assert!(matches!(
+            reject(TxRequest { position_index: 13, ..request(TransactionKind::Deposit, 1) }),
+            TxError::PositionNotOpen(13)
+        ));
+        assert!(matches!(
+            reject(TxRequest { position_index: 11, ..request(TransactionKind::Deposit, 1) }),
+            TxError::BalanceOverflow(11)
+        ));
+        assert!(matches!(reject(request(TransactionKind::Transfer, 1)), TxError::MissingReceiver));
+        assert!(matches!(
+            reject(TxRequest { to_position_index: Some(1 << POSITION_TREE_DEPTH), ..request(TransactionKind::Transfer, 1) }),
+            TxError::IndexOutOfRange(_)
+        ));
+        assert!(matches!(
+            reject(TxRequest { to_position_index: Some(10), ..request(TransactionKind::Transfer, 1) }),
+            TxError::SelfTransfer
+        ));
+        assert!(matches!(
+            reject(TxRequest { to_position_index: Some(13), ..request(TransactionKind::Transfer, 1) }),
+            TxError::PositionNotOpen(13)
+        ));
+        assert!(matches!(
+            reject(TxRequest { to_position_index: Some(11), ..request(TransactionKind::Transfer, 1) }),
+            TxError::BalanceOverflow(11)
+        ));
+        assert!(matches!(reject(request(TransactionKind::Withdraw, 1)), TxError::MissingAuthorization));
+        assert!(matches!(
+            reject(TxRequest { nonce: F::ONE, ..request(TransactionKind::Withdraw, 1) }),
+            TxError::NonceMismatch { expected: 0, got: 1 }
+        ));
+        assert!(matches!(
+            reject(request(TransactionKind::Withdraw, 101)),
+            TxError::InsufficientBalance { balance: 100, amount: 101 }
+        ));
+
+        assert_eq!(sequencer.state.root(), root);
+    }
 }
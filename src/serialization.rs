@@ -0,0 +1,124 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use plonky2::field::extension::Extendable;
+use plonky2::hash::hash_types::RichField;
+use plonky2::plonk::circuit_data::{CommonCircuitData, VerifierOnlyCircuitData};
+use plonky2::plonk::config::GenericConfig;
+use plonky2::plonk::proof::ProofWithPublicInputs;
+use plonky2::util::serialization::gate_serialization::default::DefaultGateSerializer;
+
+// Binary files holding the circuit and proof artifacts consumed by the `plonky2-circom` tool.
+
+pub fn write_common_data<F: RichField + Extendable<D>, const D: usize, P: AsRef<Path>>(
+    path: P,
+    common: &CommonCircuitData<F, D>,
+) -> Result<()> {
+    let bytes = common
+        .to_bytes(&DefaultGateSerializer)
+        .map_err(|_| anyhow!("failed to serialize the common circuit data"))?;
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+pub fn read_common_data<F: RichField + Extendable<D>, const D: usize, P: AsRef<Path>>(
+    path: P,
+) -> Result<CommonCircuitData<F, D>> {
+    CommonCircuitData::from_bytes(fs::read(path)?, &DefaultGateSerializer)
+        .map_err(|_| anyhow!("failed to deserialize the common circuit data"))
+}
+
+pub fn write_verifier_data<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    const D: usize,
+    P: AsRef<Path>,
+>(
+    path: P,
+    verifier_only: &VerifierOnlyCircuitData<C, D>,
+) -> Result<()> {
+    let bytes = verifier_only
+        .to_bytes()
+        .map_err(|_| anyhow!("failed to serialize the verifier data"))?;
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+pub fn read_verifier_data<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    const D: usize,
+    P: AsRef<Path>,
+>(
+    path: P,
+) -> Result<VerifierOnlyCircuitData<C, D>> {
+    VerifierOnlyCircuitData::from_bytes(fs::read(path)?)
+        .map_err(|_| anyhow!("failed to deserialize the verifier data"))
+}
+
+pub fn write_proof<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    const D: usize,
+    P: AsRef<Path>,
+>(
+    path: P,
+    proof: &ProofWithPublicInputs<F, C, D>,
+) -> Result<()> {
+    fs::write(path, proof.to_bytes())?;
+    Ok(())
+}
+
+/// Proofs are compressed against their circuit, so `common` is needed to read them back.
+pub fn read_proof<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    const D: usize,
+    P: AsRef<Path>,
+>(
+    path: P,
+    common: &CommonCircuitData<F, D>,
+) -> Result<ProofWithPublicInputs<F, C, D>> {
+    ProofWithPublicInputs::from_bytes(fs::read(path)?, common)
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::field::types::Field;
+    use plonky2::gates::noop::NoopGate;
+    use plonky2::iop::witness::{PartialWitness, WitnessWrite};
+    use plonky2::plonk::circuit_builder::CircuitBuilder;
+    use plonky2::plonk::circuit_data::CircuitConfig;
+
+    use crate::test_utils::unique_temp_dir;
+    use crate::types::{Cbn128, D, F};
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+        for _ in 0..100 {
+            builder.add_gate(NoopGate, vec![]);
+        }
+        let t = builder.add_virtual_public_input();
+        let data = builder.build::<Cbn128>();
+        let mut pw = PartialWitness::new();
+        pw.set_target(t, F::from_canonical_u64(7));
+        let proof = data.prove(pw).unwrap();
+
+        let dir = unique_temp_dir();
+        write_common_data(dir.join("common.bin"), &data.common).unwrap();
+        write_verifier_data(dir.join("verifier_data.bin"), &data.verifier_only).unwrap();
+        write_proof(dir.join("proof.bin"), &proof).unwrap();
+
+        let common = read_common_data::<F, D, _>(dir.join("common.bin")).unwrap();
+        let verifier_only =
+            read_verifier_data::<F, Cbn128, D, _>(dir.join("verifier_data.bin")).unwrap();
+        let read = read_proof::<F, Cbn128, D, _>(dir.join("proof.bin"), &common).unwrap();
+        assert_eq!(common, data.common);
+        assert_eq!(verifier_only.circuit_digest, data.verifier_only.circuit_digest);
+        assert_eq!(read.public_inputs, proof.public_inputs);
+    }
+}
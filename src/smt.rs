@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use anyhow::{ensure, Result};
+use plonky2::hash::hash_types::{HashOut, RichField};
+use plonky2::hash::merkle_proofs::MerkleProof;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+
+/// The deepest tree the circuits support. They decompose a leaf index with `split_le`, which is
+/// only unique below the 64 bits of a Goldilocks element.
+pub const MAX_DEPTH: usize = 63;
+
+/// A Poseidon Merkle tree of `2^depth` leaves that only stores the non-empty ones. Subtrees
+/// without any leaf are represented by precomputed empty-subtree hashes, so the root, the
+/// proofs and the updates all cost `depth` hashes however large the tree is. Proofs have the
+/// same shape as those of `MerkleTree`, so the circuits check them with
+/// `verify_merkle_proof_to_cap`.
+#[derive(Debug, Clone)]
+pub struct SparseMerkleTree<F: RichField> {
+    depth: usize,
+    empty_leaf: Vec<F>,
+    // empty_hashes[level] is the root of an empty subtree of height `level`.
+    empty_hashes: Vec<HashOut<F>>,
+    leaves: HashMap<usize, Vec<F>>,
+    // Non-empty nodes by (level, index), level 0 being the leaf hashes.
+    nodes: HashMap<(usize, usize), HashOut<F>>,
+}
+
+impl<F: RichField> SparseMerkleTree<F> {
+    pub fn new(depth: usize, empty_leaf: Vec<F>) -> Result<Self> {
+        ensure!(depth <= MAX_DEPTH, "depth {} exceeds the maximum of {}", depth, MAX_DEPTH);
+        let mut empty_hashes = vec![PoseidonHash::hash_or_noop(&empty_leaf)];
+        for level in 0..depth {
+            let h = empty_hashes[level];
+            empty_hashes.push(PoseidonHash::two_to_one(h, h));
+        }
+        Ok(Self {
+            depth,
+            empty_leaf,
+            empty_hashes,
+            leaves: HashMap::new(),
+            nodes: HashMap::new(),
+        })
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn root(&self) -> HashOut<F> {
+        self.node(self.depth, 0)
+    }
+
+    fn node(&self, level: usize, index: usize) -> HashOut<F> {
+        self.nodes
+            .get(&(level, index))
+            .copied()
+            .unwrap_or(self.empty_hashes[level])
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.leaves.contains_key(&index)
+    }
+
+    pub fn get(&self, index: usize) -> &[F] {
+        self.leaves.get(&index).unwrap_or(&self.empty_leaf)
+    }
+
+    /// Number of non-empty leaves.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Sets the leaf at `index`. Setting the empty leaf removes it.
+    pub fn set(&mut self, index: usize, leaf: Vec<F>) -> Result<()> {
+        ensure!(index >> self.depth == 0, "index {} out of range", index);
+
+        let mut h = PoseidonHash::hash_or_noop(&leaf);
+        if leaf == self.empty_leaf {
+            self.leaves.remove(&index);
+        } else {
+            self.leaves.insert(index, leaf);
+        }
+
+        let mut i = index;
+        for level in 0..=self.depth {
+            if h == self.empty_hashes[level] {
+                self.nodes.remove(&(level, i));
+            } else {
+                self.nodes.insert((level, i), h);
+            }
+            if level < self.depth {
+                h = if i & 1 == 0 {
+                    PoseidonHash::two_to_one(h, self.node(level, i ^ 1))
+                } else {
+                    PoseidonHash::two_to_one(self.node(level, i ^ 1), h)
+                };
+                i >>= 1;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn prove(&self, index: usize) -> MerkleProof<F, PoseidonHash> {
+        MerkleProof {
+            siblings: (0..self.depth)
+                .map(|level| self.node(level, (index >> level) ^ 1))
+                .collect(),
+        }
+    }
+
+    /// Proof that `index` holds the empty leaf, i.e. that it is free for a new account.
+    pub fn prove_non_membership(&self, index: usize) -> Result<MerkleProof<F, PoseidonHash>> {
+        ensure!(index >> self.depth == 0, "index {} out of range", index);
+        ensure!(!self.contains(index), "index {} is occupied", index);
+        Ok(self.prove(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::field::types::Field;
+    use plonky2::hash::merkle_tree::MerkleTree;
+
+    use crate::sequencer::root_from_merkle_proof;
+    use crate::types::F;
+
+    use super::*;
+
+    fn leaf(index: usize) -> Vec<F> {
+        (0..6).map(|i| F::from_canonical_usize(6 * index + i + 1)).collect()
+    }
+
+    #[test]
+    fn test_matches_merkle_tree() {
+        let mut smt = SparseMerkleTree::<F>::new(4, vec![F::ZERO; 6]).unwrap();
+        let mut leaves = vec![vec![F::ZERO; 6]; 16];
+        for index in [0, 5, 6, 15] {
+            leaves[index] = leaf(index);
+            smt.set(index, leaves[index].clone()).unwrap();
+        }
+        let tree = MerkleTree::<F, PoseidonHash>::new(leaves, 0);
+        assert_eq!(smt.root(), tree.cap.0[0]);
+        for index in 0..16 {
+            assert_eq!(smt.prove(index).siblings, tree.prove(index).siblings);
+        }
+    }
+
+    #[test]
+    fn test_deep_tree() {
+        let mut smt = SparseMerkleTree::<F>::new(40, vec![F::ZERO; 6]).unwrap();
+        let empty_root = smt.root();
+        let index = (1 << 39) + 12345;
+        let leaf = leaf(index);
+
+        let proof = smt.prove_non_membership(index).unwrap();
+        assert_eq!(root_from_merkle_proof(vec![F::ZERO; 6], index, &proof), empty_root);
+
+        smt.set(index, leaf.clone()).unwrap();
+        assert_eq!(root_from_merkle_proof(leaf, index, &proof), smt.root());
+        assert!(smt.prove_non_membership(index).is_err());
+        assert!(smt.set(1 << 40, vec![F::ONE; 6]).is_err());
+
+        smt.set(index, vec![F::ZERO; 6]).unwrap();
+        assert_eq!(smt.root(), empty_root);
+        assert!(smt.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_depth_beyond_circuits() {
+        assert!(SparseMerkleTree::<F>::new(MAX_DEPTH, vec![F::ZERO; 6]).is_ok());
+        assert!(SparseMerkleTree::<F>::new(MAX_DEPTH + 1, vec![F::ZERO; 6]).is_err());
+    }
+}
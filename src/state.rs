@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use anyhow::{anyhow, ensure, Result};
+use plonky2::field::extension::Extendable;
+use plonky2::field::types::Field;
+use plonky2::hash::hash_types::{HashOut, RichField};
+use plonky2::hash::merkle_proofs::MerkleProof;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+use serde::{Deserialize, Serialize};
+
+use crate::smt::SparseMerkleTree;
+use crate::types::{Insertion, Position, Transaction, TransferReceiver};
+use crate::types::{TransactionKind, BALANCE_BITS};
+
+/// The position tree kept by the sequencer, backed by a `SparseMerkleTree`, so only the opened
+/// positions are stored and a transaction only rehashes the path from its leaf to the root.
+/// Owners keep their private keys; only the public keys are in the positions.
+#[derive(Debug, Clone)]
+pub struct PositionState<F: RichField> {
+    tree: SparseMerkleTree<F>,
+    positions: HashMap<usize, Position<F>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+struct Snapshot<F: RichField> {
+    depth: usize,
+    positions: Vec<(usize, Position<F>)>,
+}
+
+fn empty_position<F: RichField>() -> Position<F> {
+    Position {
+        pubkey: HashOut { elements: [F::ZERO; 4] },
+        balance: F::ZERO,
+        nonce: F::ZERO,
+    }
+}
+
+impl<F: RichField> PositionState<F> {
+    /// A tree of `2^depth` empty positions. The depth is at most `smt::MAX_DEPTH`.
+    pub fn new(depth: usize) -> Result<Self> {
+        Ok(Self {
+            tree: SparseMerkleTree::new(depth, empty_position::<F>().to_leaf())?,
+            positions: HashMap::new(),
+        })
+    }
+
+    pub fn depth(&self) -> usize {
+        self.tree.depth()
+    }
+
+    pub fn root(&self) -> HashOut<F> {
+        self.tree.root()
+    }
+
+    /// The position at `index`, empty if it was never opened.
+    pub fn position(&self, index: usize) -> Position<F> {
+        self.positions.get(&index).copied().unwrap_or_else(empty_position)
+    }
+
+    /// Whether the position at `index` was opened for an owner, by `open_position` or `insert`.
+    /// Only open positions can be credited: crediting an empty leaf gives it a balance nobody
+    /// can spend, and it can no longer be inserted into.
+    pub fn is_open(&self, index: usize) -> bool {
+        matches!(self.positions.get(&index), Some(p) if p.pubkey != empty_position::<F>().pubkey)
+    }
+
+    pub fn prove(&self, index: usize) -> MerkleProof<F, PoseidonHash> {
+        self.tree.prove(index)
+    }
+
+    fn in_range(&self, index: usize) -> bool {
+        index >> self.depth() == 0
+    }
+
+    /// Sets the position at `index`. Setting the empty position removes it, as in the tree.
+    fn set_position(&mut self, index: usize, position: Position<F>) {
+        self.tree.set(index, position.to_leaf()).unwrap();
+        if position == empty_position() {
+            self.positions.remove(&index);
+        } else {
+            self.positions.insert(index, position);
+        }
+    }
+
+    /// Assigns the position at `index` to the owner of `pubkey`.
+    pub fn open_position(&mut self, index: usize, pubkey: HashOut<F>, balance: F) -> Result<()> {
+        ensure!(self.in_range(index), "position {} out of range", index);
+        ensure!(balance.to_canonical_u64() < 1 << BALANCE_BITS, "balance out of range");
+        self.set_position(
+            index,
+            Position {
+                pubkey,
+                balance,
+                nonce: F::ZERO,
+            },
+        );
+        Ok(())
+    }
+
+    /// Opens an empty position for the owner of `pubkey`, with a zero balance, and returns the
+    /// witness of `insert_circuit`.
+    pub fn insert(&mut self, index: usize, pubkey: HashOut<F>) -> Result<Insertion<F>> {
+        ensure!(self.in_range(index), "position {} out of range", index);
+        let merkle_proof = self.tree.prove_non_membership(index)?;
+        let root_before = self.root();
+        self.set_position(
+            index,
+            Position {
+                pubkey,
+                balance: F::ZERO,
+                nonce: F::ZERO,
+            },
+        );
+        Ok(Insertion {
+            root_before,
+            root_after: self.root(),
+            merkle_proof,
+            pubkey,
+            position_index: index,
+            position_tree_depth: self.depth(),
+        })
+    }
+
+    /// Applies a transaction and returns the witness of its circuit. Withdrawals and transfers
+    /// come without the private key, which the owner attaches before proving; transfers also
+    /// need the receiving position.
+    pub fn apply<const D: usize>(
+        &mut self,
+        transaction_type: TransactionKind,
+        position_index: usize,
+        amount: F,
+        to_position_index: Option<usize>,
+    ) -> Result<Transaction<F, D>>
+    where
+        F: Extendable<D>,
+    {
+        ensure!(self.in_range(position_index), "position {} out of range", position_index);
+        ensure!(amount.to_canonical_u64() < 1 << BALANCE_BITS, "amount out of range");
+
+        let position = self.position(position_index);
+        let root_before = self.root();
+        let merkle_proof = self.prove(position_index);
+        let balance = position.balance.to_canonical_u64();
+        let amount_u64 = amount.to_canonical_u64();
+
+        let transfer_to = match transaction_type {
+            TransactionKind::Deposit => {
+                ensure!(self.is_open(position_index), "position {} is not open", position_index);
+                ensure!(balance + amount_u64 < 1 << BALANCE_BITS, "balance overflow");
+                self.set_position(
+                    position_index,
+                    Position { balance: position.balance + amount, ..position },
+                );
+                None
+            }
+            TransactionKind::Withdraw | TransactionKind::Transfer => {
+                ensure!(amount_u64 <= balance, "insufficient balance");
+                let to_index = if transaction_type == TransactionKind::Transfer {
+                    let to_index = to_position_index.ok_or_else(|| anyhow!("transfer without a receiver"))?;
+                    ensure!(self.in_range(to_index), "position {} out of range", to_index);
+                    ensure!(to_index != position_index, "transfer to the sending position");
+                    ensure!(self.is_open(to_index), "position {} is not open", to_index);
+                    ensure!(
+                        self.position(to_index).balance.to_canonical_u64() + amount_u64 < 1 << BALANCE_BITS,
+                        "balance overflow"
+                    );
+                    Some(to_index)
+                } else {
+                    None
+                };
+                self.set_position(
+                    position_index,
+                    Position {
+                        balance: position.balance - amount,
+                        nonce: position.nonce + F::ONE,
+                        ..position
+                    },
+                );
+
+                let transfer_to = to_index.map(|to_index| {
+                    let receiver = self.position(to_index);
+                    let root_intermediate = self.root();
+                    let receiver_proof = self.prove(to_index);
+                    self.set_position(
+                        to_index,
+                        Position { balance: receiver.balance + amount, ..receiver },
+                    );
+                    TransferReceiver {
+                        root_intermediate,
+                        merkle_proof: receiver_proof,
+                        pubkey: receiver.pubkey,
+                        balance_before: receiver.balance,
+                        nonce: receiver.nonce,
+                        position_index: to_index,
+                    }
+                });
+                transfer_to
+            }
+            _ => return Err(anyhow!("invalid transaction type {:?}", transaction_type)),
+        };
+
+        Ok(Transaction {
+            transaction_type,
+            root_before,
+            root_after: self.root(),
+            merkle_proof,
+            pubkey: position.pubkey,
+            balance_before: position.balance,
+            nonce: position.nonce,
+            position_index,
+            amount,
+            position_tree_depth: self.depth(),
+            private_key: None,
+            transfer_to,
+        })
+    }
+
+    /// The witness `apply` would return, leaving the state as it was.
+    pub fn preview<const D: usize>(
+        &mut self,
+        transaction_type: TransactionKind,
+        position_index: usize,
+        amount: F,
+        to_position_index: Option<usize>,
+    ) -> Result<Transaction<F, D>>
+    where
+        F: Extendable<D>,
+    {
+        let tx = self.apply(transaction_type, position_index, amount, to_position_index)?;
+        self.revert(&tx);
+        Ok(tx)
+    }
+
+    /// Undoes `tx`, which must be the last transaction applied.
+    pub fn revert<const D: usize>(&mut self, tx: &Transaction<F, D>)
+    where
+        F: Extendable<D>,
+    {
+        if let Some(to) = &tx.transfer_to {
+            self.set_position(
+                to.position_index,
+                Position {
+                    pubkey: to.pubkey,
+                    balance: to.balance_before,
+                    nonce: to.nonce,
+                },
+            );
+        }
+        self.set_position(
+            tx.position_index,
+            Position {
+                pubkey: tx.pubkey,
+                balance: tx.balance_before,
+                nonce: tx.nonce,
+            },
+        );
+    }
+
+    /// Writes the positions to `path` as JSON. The tree is rebuilt by `restore`.
+    pub fn snapshot<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let snapshot = Snapshot {
+            depth: self.depth(),
+            positions: self.positions.iter().map(|(&i, &p)| (i, p)).collect(),
+        };
+        serde_json::to_writer(BufWriter::new(File::create(path)?), &snapshot)?;
+        Ok(())
+    }
+
+    /// Rebuilds the state written by `snapshot`, checking each position as `open_position` does.
+    pub fn restore<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let snapshot: Snapshot<F> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let mut state = Self::new(snapshot.depth)?;
+        for (index, position) in snapshot.positions {
+            ensure!(state.in_range(index), "position {} out of range", index);
+            ensure!(
+                position.balance.to_canonical_u64() < 1 << BALANCE_BITS,
+                "balance of position {} out of range",
+                index
+            );
+            state.set_position(index, position);
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::hash::merkle_tree::MerkleTree;
+
+    use crate::sequencer::root_from_merkle_proof;
+    use crate::test_utils::unique_temp_dir;
+    use crate::types::{public_key, D, F};
+
+    use super::*;
+
+    #[test]
+    fn test_incremental_root_matches_merkle_tree() {
+        let mut state = PositionState::<F>::new(4).unwrap();
+        state.open_position(3, public_key([F::ONE; 4]), F::from_canonical_u64(50)).unwrap();
+        state.open_position(9, public_key([F::TWO; 4]), F::ZERO).unwrap();
+        let tx = state.apply::<D>(TransactionKind::Transfer, 3, F::from_canonical_u64(20), Some(9)).unwrap();
+
+        let leaves = (0..16).map(|i| state.position(i).to_leaf()).collect();
+        let tree = MerkleTree::<F, PoseidonHash>::new(leaves, 0);
+        assert_eq!(state.root(), tree.cap.0[0]);
+        assert_eq!(tx.root_after, tree.cap.0[0]);
+        assert_eq!(state.prove(9).siblings, tree.prove(9).siblings);
+        assert_eq!(
+            root_from_merkle_proof(state.position(3).to_leaf(), 3, &state.prove(3)),
+            state.root()
+        );
+    }
+
+    #[test]
+    fn test_preview_leaves_state_unchanged() {
+        let mut state = PositionState::<F>::new(4).unwrap();
+        state.open_position(3, public_key([F::ONE; 4]), F::from_canonical_u64(50)).unwrap();
+        state.open_position(9, public_key([F::TWO; 4]), F::ZERO).unwrap();
+        let root = state.root();
+
+        let preview = state.preview::<D>(TransactionKind::Transfer, 3, F::from_canonical_u64(20), Some(9)).unwrap();
+        assert_eq!(state.root(), root);
+        assert_eq!(state.position(9).balance, F::ZERO);
+
+        let tx = state.apply::<D>(TransactionKind::Transfer, 3, F::from_canonical_u64(20), Some(9)).unwrap();
+        assert_eq!(preview.root_after, tx.root_after);
+    }
+
+    #[test]
+    fn test_rejects_invalid_transactions() {
+        let mut state = PositionState::<F>::new(4).unwrap();
+        state.open_position(3, public_key([F::ONE; 4]), F::from_canonical_u64(50)).unwrap();
+        let root = state.root();
+        assert!(state.apply::<D>(TransactionKind::Withdraw, 3, F::from_canonical_u64(51), None).is_err());
+        assert!(state.apply::<D>(TransactionKind::Withdraw, 4, F::ONE, None).is_err());
+        assert!(state.apply::<D>(TransactionKind::Deposit, 16, F::ONE, None).is_err());
+        // Neither side of a credit can be an empty position.
+        assert!(state.apply::<D>(TransactionKind::Deposit, 4, F::ONE, None).is_err());
+        assert!(state.apply::<D>(TransactionKind::Transfer, 3, F::ONE, Some(4)).is_err());
+        assert!(state.apply::<D>(TransactionKind::Transfer, 3, F::ONE, None).is_err());
+        assert_eq!(state.root(), root);
+    }
+
+    #[test]
+    fn test_insert_into_deep_tree() {
+        let mut state = PositionState::<F>::new(32).unwrap();
+        let index = (1 << 31) + 7;
+        let insertion = state.insert(index, public_key([F::ONE; 4])).unwrap();
+        assert!(state.insert(index, public_key([F::TWO; 4])).is_err());
+        assert_eq!(insertion.root_after, state.root());
+
+        let tx = state.apply::<D>(TransactionKind::Deposit, index, F::from_canonical_u64(5), None).unwrap();
+        assert_eq!(tx.root_before, insertion.root_after);
+        assert_eq!(tx.merkle_proof.siblings.len(), 32);
+        assert_eq!(state.position(index).balance, F::from_canonical_u64(5));
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut state = PositionState::<F>::new(4).unwrap();
+        state.open_position(3, public_key([F::ONE; 4]), F::from_canonical_u64(50)).unwrap();
+        state.apply::<D>(TransactionKind::Withdraw, 3, F::ONE, None).unwrap();
+
+        let path = unique_temp_dir().join("position_state_snapshot.json");
+        state.snapshot(&path).unwrap();
+        let mut restored = PositionState::<F>::restore(&path).unwrap();
+        assert_eq!(restored.root(), state.root());
+
+        let tx = restored.apply::<D>(TransactionKind::Withdraw, 3, F::ONE, None).unwrap();
+        assert_eq!(tx.nonce, F::ONE);
+    }
+
+    #[test]
+    fn test_restore_rejects_balance_out_of_range() {
+        let position = Position {
+            pubkey: public_key([F::ONE; 4]),
+            balance: F::from_canonical_u64(1 << BALANCE_BITS),
+            nonce: F::ZERO,
+        };
+        let path = unique_temp_dir().join("position_state_snapshot.json");
+        let snapshot = Snapshot { depth: 4, positions: vec![(3, position)] };
+        serde_json::to_writer(File::create(&path).unwrap(), &snapshot).unwrap();
+        assert!(PositionState::<F>::restore(&path).is_err());
+    }
+
+    #[test]
+    fn test_revert_removes_empty_positions() {
+        let mut state = PositionState::<F>::new(4).unwrap();
+        state.open_position(3, public_key([F::ONE; 4]), F::from_canonical_u64(50)).unwrap();
+        let tx = state.apply::<D>(TransactionKind::Withdraw, 3, F::ONE, None).unwrap();
+
+        // Reverting to an empty position leaves neither a leaf nor an entry behind.
+        let empty = empty_position::<F>();
+        state.revert(&Transaction { pubkey: empty.pubkey, balance_before: empty.balance, ..tx });
+        assert!(state.positions.is_empty());
+        assert_eq!(state.root(), PositionState::<F>::new(4).unwrap().root());
+    }
+}
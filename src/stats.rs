@@ -0,0 +1,175 @@
+use plonky2::field::extension::Extendable;
+use plonky2::hash::hash_types::RichField;
+use plonky2::hash::merkle_proofs::MerkleProof;
+use plonky2::plonk::config::GenericConfig;
+use plonky2::plonk::proof::ProofWithPublicInputs;
+use serde::{Deserialize, Serialize};
+
+use crate::formats::circom_input_shapes;
+use crate::verifier::VerifierConfig;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SectionStats {
+    pub bytes: usize,
+    pub field_elements: usize,
+}
+
+impl std::ops::Add for SectionStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            bytes: self.bytes + other.bytes,
+            field_elements: self.field_elements + other.field_elements,
+        }
+    }
+}
+
+impl std::iter::Sum for SectionStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |a, b| a + b)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct QueryRoundStats {
+    pub initial_trees: SectionStats,
+    pub steps: Vec<SectionStats>,
+}
+
+/// Size of a proof, per section, as serialized by `ProofWithPublicInputs::to_bytes` and as
+/// field elements, together with the input signals the Circom verifier expects for it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProofStats {
+    pub caps: SectionStats,
+    pub openings: SectionStats,
+    pub fri_commit_caps: SectionStats,
+    pub query_rounds: Vec<QueryRoundStats>,
+    pub final_poly: SectionStats,
+    pub pow_witness: SectionStats,
+    pub public_inputs: SectionStats,
+    pub circom_input_signals: usize,
+    pub circom_public_signals: usize,
+}
+
+impl ProofStats {
+    pub fn new<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
+        pwpi: &ProofWithPublicInputs<F, C, D>,
+        conf: &VerifierConfig,
+    ) -> Self {
+        let hashes = |n: usize| SectionStats {
+            bytes: n * conf.hash_size,
+            field_elements: n * 4,
+        };
+        let fields = |n: usize| SectionStats {
+            bytes: n * conf.field_size,
+            field_elements: n,
+        };
+        let exts = |n: usize| SectionStats {
+            bytes: n * conf.ext_field_size,
+            field_elements: n * D,
+        };
+        // Merkle proofs are prefixed with their length.
+        let merkle_proof = |p: &MerkleProof<F, C::Hasher>| SectionStats {
+            bytes: conf.merkle_height_size,
+            field_elements: 0,
+        } + hashes(p.siblings.len());
+
+        let proof = &pwpi.proof;
+        let openings = &proof.openings;
+        let fri = &proof.opening_proof;
+        let num_openings = openings.constants.len()
+            + openings.plonk_sigmas.len()
+            + openings.wires.len()
+            + openings.plonk_zs.len()
+            + openings.plonk_zs_next.len()
+            + openings.partial_products.len()
+            + openings.quotient_polys.len();
+
+        let query_rounds = fri
+            .query_round_proofs
+            .iter()
+            .map(|round| QueryRoundStats {
+                initial_trees: round
+                    .initial_trees_proof
+                    .evals_proofs
+                    .iter()
+                    .map(|(v, p)| fields(v.len()) + merkle_proof(p))
+                    .sum(),
+                steps: round
+                    .steps
+                    .iter()
+                    .map(|step| exts(step.evals.len()) + merkle_proof(&step.merkle_proof))
+                    .collect(),
+            })
+            .collect();
+
+        Self {
+            caps: hashes(
+                proof.wires_cap.0.len()
+                    + proof.plonk_zs_partial_products_cap.0.len()
+                    + proof.quotient_polys_cap.0.len(),
+            ),
+            openings: exts(num_openings),
+            fri_commit_caps: fri
+                .commit_phase_merkle_caps
+                .iter()
+                .map(|cap| hashes(cap.0.len()))
+                .sum(),
+            query_rounds,
+            final_poly: exts(fri.final_poly.coeffs.len()),
+            pow_witness: fields(1),
+            public_inputs: fields(pwpi.public_inputs.len()),
+            circom_input_signals: circom_input_shapes(conf)
+                .iter()
+                .map(|(_, shape)| shape.iter().product::<usize>())
+                .sum(),
+            circom_public_signals: conf.num_public_inputs,
+        }
+    }
+
+    pub fn total(&self) -> SectionStats {
+        self.caps
+            + self.openings
+            + self.fri_commit_caps
+            + self
+                .query_rounds
+                .iter()
+                .map(|round| {
+                    round.initial_trees + round.steps.iter().copied().sum::<SectionStats>()
+                })
+                .sum::<SectionStats>()
+            + self.final_poly
+            + self.pow_witness
+            + self.public_inputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::test_utils::{dummy_proof, final_config};
+    use crate::types::{Cbn128, D, F};
+    use crate::verifier::verifier_config_from_common;
+
+    use super::*;
+
+    #[test]
+    fn test_proof_stats() -> Result<()> {
+        let (pwpi, _, common) = dummy_proof::<F, Cbn128, D>(&final_config(), 4_000, 1)?;
+
+        let conf = verifier_config_from_common(&common)?;
+        let stats = ProofStats::new(&pwpi, &conf);
+        assert_eq!(stats.total().bytes, pwpi.to_bytes().len());
+        // Every field element of the proof is an input signal of the Circom verifier.
+        assert_eq!(stats.total().field_elements, stats.circom_input_signals);
+        assert_eq!(stats.query_rounds.len(), 10);
+        assert_eq!(stats.query_rounds[0].steps.len(), 2);
+        assert_eq!(stats.circom_public_signals, 1);
+
+        let report: ProofStats = serde_json::from_str(&serde_json::to_string(&stats)?)?;
+        assert_eq!(report, stats);
+        Ok(())
+    }
+}
@@ -90,3 +90,5 @@ function K_IS(i) {
   return k_is[i];
 }
 function NUM_PUBLIC_INPUTS() { return $NUM_PUBLIC_INPUTS; }
+function PUBLIC_INPUTS_PER_SIGNAL() { return $PUBLIC_INPUTS_PER_SIGNAL; }
+function NUM_PUBLIC_SIGNALS() { return (NUM_PUBLIC_INPUTS() + PUBLIC_INPUTS_PER_SIGNAL() - 1) \ PUBLIC_INPUTS_PER_SIGNAL(); }
@@ -0,0 +1,49 @@
+// This file was generated by verifier.rs
+
+// SPDX-License-Identifier: GPL-3.0
+pragma solidity ^0.6.11;
+
+// The `Verifier` contract exported by `snarkjs zkey export solidityverifier`.
+interface IGroth16Verifier {
+    function verifyProof(
+        uint[2] calldata a,
+        uint[2][2] calldata b,
+        uint[2] calldata c,
+        uint[$NUM_PUBLIC_SIGNALS] calldata input
+    ) external view returns (bool);
+}
+
+// Verifies a Groth16 proof of the Circom plonky2 verifier against plonky2 public inputs.
+contract Plonky2Verifier {
+    // Order of Goldilocks field
+    uint64 constant ORDER = 18446744069414584321;
+
+    IGroth16Verifier public immutable verifier;
+
+    constructor(address groth16Verifier) public {
+        verifier = IGroth16Verifier(groth16Verifier);
+    }
+
+    // Each public signal of the Circom verifier packs $PUBLIC_INPUTS_PER_SIGNAL public input(s), the
+    // first one in the low 64 bits.
+    function publicSignals(uint64[$NUM_PUBLIC_INPUTS] memory publicInputs)
+        public
+        pure
+        returns (uint[$NUM_PUBLIC_SIGNALS] memory signals)
+    {
+        for (uint i = 0; i < $NUM_PUBLIC_INPUTS; i++) {
+            require(publicInputs[i] < ORDER, "plonky2-non-canonical-input");
+            signals[i / $PUBLIC_INPUTS_PER_SIGNAL] |=
+                uint(publicInputs[i]) << (64 * (i % $PUBLIC_INPUTS_PER_SIGNAL));
+        }
+    }
+
+    function verifyProof(
+        uint64[$NUM_PUBLIC_INPUTS] memory publicInputs,
+        uint[2] memory a,
+        uint[2][2] memory b,
+        uint[2] memory c
+    ) public view returns (bool) {
+        return verifier.verifyProof(a, b, c, publicSignals(publicInputs));
+    }
+}
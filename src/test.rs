@@ -6,13 +6,25 @@ mod tests {
     use plonky2::{plonk::{config::{GenericConfig, PoseidonGoldilocksConfig}, circuit_data::CircuitConfig, circuit_builder::CircuitBuilder}, fri::{FriConfig, reduction_strategies::FriReductionStrategy}};
 
     // use crate::{config::KeccakGoldilocksConfig2};
-    use crate::{verifier::{generate_proof_base64, generate_verifier_config, generate_circom_verifier}, types::{verify_proof, Cbn128, POSITION_TREE_DEPTH}, sequencer::generate_transactions};
+    use crate::{verifier::{generate_proof_base64, generate_verifier_config, generate_circom_verifier, generate_solidity_wrapper}, types::{verify_proof, Cbn128, POSITION_TREE_DEPTH}, sequencer::generate_transactions};
     use crate::recursive::recursive_proof;
     use crate::types::{F, C, D};
     use crate::deposit::{deposit_circuit, make_deposit_proof};
+    use crate::withdraw::make_withdraw_proof;
+    use crate::transaction::make_transaction_proof;
+    use crate::transfer::make_transfer_proof;
+    use crate::aggregation::{make_vd_proof, verifier_data_tree};
+    use crate::test_utils::dummy_proof;
+    use crate::recursive::{final_layer_proof, final_layer_shape, transaction_verifier_data_tree, TRANSACTION_VD_INDEX, TRANSFER_VD_INDEX};
+    use crate::types::{Position, ProofTuple, Transaction, TransactionKind, BALANCE_BITS};
+    use crate::sequencer::root_from_merkle_proof;
+    use plonky2::field::types::Field;
+    use std::panic::{self, AssertUnwindSafe};
+    use log::info;
 
     #[test]
     fn test_resursive_single_proof_to_circom() {
+        let _ = env_logger::builder().is_test(true).try_init();
         let time = std::time::Instant::now();
 
         let standard_config = CircuitConfig::standard_recursion_config();
@@ -39,23 +51,23 @@ mod tests {
             },
             ..high_rate_config
         };
-        let deposit_tx = generate_transactions::<F, D>(vec![0]).unwrap();
+        let deposit_tx = generate_transactions::<F, D>(vec![TransactionKind::Deposit]).unwrap();
 
         // println!("deposit_tx: {:?}", deposit_tx);
 
         let (pi, vd, cd) = 
             make_deposit_proof::<F, C, D>(&high_rate_config, deposit_tx[0].clone()).unwrap();
         
-        println!("make_deposit_proof time: {:?}", time.elapsed());
+        info!("make_deposit_proof time: {:?}", time.elapsed());
 
         let (pi, vd, cd) =
-        recursive_proof::<F, Cbn128, C, D>(pi, vd, cd, &final_config, None, true, true).unwrap();
-        println!("recursive_proof time: {:?}", time.elapsed());
+        recursive_proof::<F, Cbn128, C, D>(pi, vd, cd, None, &final_config, None).unwrap();
+        info!("recursive_proof time: {:?}", time.elapsed());
 
         // verify_proof(pi, vd, cd).unwrap();
 
         let conf = generate_verifier_config(&pi).unwrap();
-        let (circom_constants, circom_gates) = generate_circom_verifier(&conf, &cd, &vd).unwrap();
+        let (circom_constants, circom_gates, _) = generate_circom_verifier(&conf, &cd, &vd, None).unwrap();
 
         let mut circom_file = File::create("./circom/circuits/constants.circom").unwrap();
         circom_file.write_all(circom_constants.as_bytes()).unwrap();
@@ -74,6 +86,184 @@ mod tests {
         let mut conf_file = File::create("./circom/test/data/conf.json").unwrap();
         conf_file.write_all(serde_json::to_string(&conf).unwrap().as_ref()).unwrap();
 
+        // The wrapper deployed by the hardhat test.
+        let (wrapper, _) = generate_solidity_wrapper(&conf).unwrap();
+        std::fs::write("./circom/e2e_tests/hardhat/contracts/Plonky2Verifier.sol", wrapper).unwrap();
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_recursive_vd_proof_accepts_registered_circuits() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let vd_tree = transaction_verifier_data_tree::<F, C, D>(&standard_config).unwrap();
+
+        let txs = generate_transactions::<F, D>(vec![TransactionKind::Deposit, TransactionKind::Withdraw, TransactionKind::Transfer]).unwrap();
+        let (pi, vd, cd) =
+            make_transaction_proof::<F, C, D>(&standard_config, txs[0].clone()).unwrap();
+        let (_, deposit_outer_vd, _) = recursive_proof::<F, C, C, D>(
+            pi, vd, cd, Some(make_vd_proof(&vd_tree, TRANSACTION_VD_INDEX)), &standard_config, None
+        ).unwrap();
+
+        let (pi, vd, cd) =
+            make_transaction_proof::<F, C, D>(&standard_config, txs[1].clone()).unwrap();
+        let (_, withdraw_outer_vd, _) = recursive_proof::<F, C, C, D>(
+            pi, vd, cd, Some(make_vd_proof(&vd_tree, TRANSACTION_VD_INDEX)), &standard_config, None
+        ).unwrap();
+
+        // One outer verifier key for deposits and withdrawals.
+        assert_eq!(deposit_outer_vd.circuit_digest, withdraw_outer_vd.circuit_digest);
+
+        let (pi, vd, cd) =
+            make_transfer_proof::<F, C, D>(&standard_config, txs[2].clone()).unwrap();
+        recursive_proof::<F, C, C, D>(
+            pi, vd, cd, Some(make_vd_proof(&vd_tree, TRANSFER_VD_INDEX)), &standard_config, None
+        ).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_recursive_vd_proof_rejects_wrong_index() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let vd_tree = transaction_verifier_data_tree::<F, C, D>(&standard_config).unwrap();
+
+        let deposit_tx = generate_transactions::<F, D>(vec![TransactionKind::Deposit]).unwrap();
+        let (pi, vd, cd) =
+            make_transaction_proof::<F, C, D>(&standard_config, deposit_tx[0].clone()).unwrap();
+        recursive_proof::<F, C, C, D>(
+            pi, vd, cd, Some(make_vd_proof(&vd_tree, TRANSFER_VD_INDEX)), &standard_config, None
+        ).unwrap();
+    }
+
+    #[test]
+    fn test_final_layer_keeps_common_data() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let txs = generate_transactions::<F, D>(vec![TransactionKind::Deposit, TransactionKind::Withdraw]).unwrap();
+        let (pi, vd, cd) =
+            make_deposit_proof::<F, C, D>(&standard_config, txs[0].clone()).unwrap();
+
+        assert!(final_layer_shape::<F, C, C, D>(&pi, &vd, &cd, None, &standard_config, 10).is_err());
+        let shape = final_layer_shape::<F, C, C, D>(&pi, &vd, &cd, None, &standard_config, 13).unwrap();
+
+        let (pi, vd, cd) =
+            make_withdraw_proof::<F, C, D>(&standard_config, txs[1].clone()).unwrap();
+        let (_, _, final_cd) =
+            final_layer_proof::<F, C, C, D>(pi, vd, cd, None, &shape).unwrap();
+        assert_eq!(final_cd, shape);
+    }
+
+    #[test]
+    fn test_final_layer_pins_verifier_key() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let small = dummy_proof::<F, C, D>(&standard_config, 4_000, 4).unwrap();
+        let large = dummy_proof::<F, C, D>(&standard_config, 8_000, 4).unwrap();
+        assert_ne!(small.2.degree_bits(), large.2.degree_bits());
+
+        let shape = final_layer_shape::<F, C, C, D>(&large.0, &large.1, &large.2, None, &standard_config, 13).unwrap();
+        let (small_pi, small_vd, small_cd) =
+            final_layer_proof::<F, C, C, D>(small.0, small.1, small.2, None, &shape).unwrap();
+        let (large_pi, large_vd, large_cd) =
+            final_layer_proof::<F, C, C, D>(large.0, large.1, large.2, None, &shape).unwrap();
+        // The common data is fixed, but the verifier key still tracks the inner circuit.
+        assert_eq!(small_cd, large_cd);
+        assert_ne!(small_vd.circuit_digest, large_vd.circuit_digest);
+
+        let vd_tree = verifier_data_tree::<F, C, D>(&[small_vd.clone(), large_vd.clone()]).unwrap();
+        let (_, small_outer_vd, _) = recursive_proof::<F, C, C, D>(
+            small_pi, small_vd, small_cd, Some(make_vd_proof(&vd_tree, 0)), &standard_config, None
+        ).unwrap();
+        let (_, large_outer_vd, _) = recursive_proof::<F, C, C, D>(
+            large_pi, large_vd, large_cd, Some(make_vd_proof(&vd_tree, 1)), &standard_config, None
+        ).unwrap();
+        assert_eq!(small_outer_vd.circuit_digest, large_outer_vd.circuit_digest);
+        assert_eq!(small_outer_vd.constants_sigmas_cap, large_outer_vd.constants_sigmas_cap);
+    }
+
+    /// Ways of corrupting a valid transaction witness.
+    #[derive(Debug, Clone, Copy)]
+    enum Mutation {
+        WrongSibling,
+        WrongRootAfter,
+        WrongAmount,
+        WrongIndexBit,
+        IndexOutOfRange,
+        // A consistent witness whose new balance wraps around the field.
+        Overflow,
+        // As `Overflow`, with every operand at the edge of its own range check: withdrawing the
+        // largest amount from an empty position, or depositing it onto the largest balance.
+        EdgeOverflow,
+    }
+
+    const MUTATIONS: [Mutation; 7] = [
+        Mutation::WrongSibling,
+        Mutation::WrongRootAfter,
+        Mutation::WrongAmount,
+        Mutation::WrongIndexBit,
+        Mutation::IndexOutOfRange,
+        Mutation::Overflow,
+        Mutation::EdgeOverflow,
+    ];
+
+    fn mutate(mut tx: Transaction<F, D>, mutation: Mutation) -> Transaction<F, D> {
+        match mutation {
+            Mutation::WrongSibling => tx.merkle_proof.siblings[3].elements[0] += F::ONE,
+            Mutation::WrongRootAfter => tx.root_after.elements[0] += F::ONE,
+            Mutation::WrongAmount => tx.amount += F::ONE,
+            Mutation::WrongIndexBit => tx.position_index ^= 1 << 2,
+            Mutation::IndexOutOfRange => tx.position_index += 1 << tx.position_tree_depth,
+            Mutation::Overflow => {
+                let mut position = Position { pubkey: tx.pubkey, balance: tx.balance_before, nonce: tx.nonce };
+                if tx.transaction_type == TransactionKind::Deposit {
+                    tx.amount = F::from_canonical_u64(1 << BALANCE_BITS) - tx.balance_before;
+                    position.balance += tx.amount;
+                } else {
+                    tx.amount = tx.balance_before + F::ONE;
+                    position.balance -= tx.amount;
+                    position.nonce += F::ONE;
+                }
+                tx.root_after = root_from_merkle_proof(position.to_leaf(), tx.position_index, &tx.merkle_proof);
+            }
+            Mutation::EdgeOverflow => {
+                let max = F::from_canonical_u64((1 << BALANCE_BITS) - 1);
+                tx.balance_before = if tx.transaction_type == TransactionKind::Deposit { max } else { F::ZERO };
+                tx.amount = max;
+                let mut position = Position { pubkey: tx.pubkey, balance: tx.balance_before, nonce: tx.nonce };
+                tx.root_before = root_from_merkle_proof(position.to_leaf(), tx.position_index, &tx.merkle_proof);
+                if tx.transaction_type == TransactionKind::Deposit {
+                    position.balance += tx.amount;
+                } else {
+                    position.balance -= tx.amount;
+                    position.nonce += F::ONE;
+                }
+                tx.root_after = root_from_merkle_proof(position.to_leaf(), tx.position_index, &tx.merkle_proof);
+            }
+        }
+        tx
+    }
+
+    /// Proves `tx`, treating a panic during witness generation or proving as a failure.
+    fn proves(prove: fn(&CircuitConfig, Transaction<F, D>) -> Result<ProofTuple<F, C, D>>, tx: Transaction<F, D>) -> bool {
+        let config = CircuitConfig::standard_recursion_config();
+        matches!(panic::catch_unwind(AssertUnwindSafe(|| prove(&config, tx))), Ok(Ok(_)))
+    }
+
+    fn assert_mutations_rejected(
+        prove: fn(&CircuitConfig, Transaction<F, D>) -> Result<ProofTuple<F, C, D>>,
+        kind: TransactionKind,
+    ) {
+        let tx = generate_transactions::<F, D>(vec![kind]).unwrap()[0].clone();
+        assert!(proves(prove, tx.clone()), "valid {:?} rejected", kind);
+        for mutation in MUTATIONS {
+            assert!(!proves(prove, mutate(tx.clone(), mutation)), "{:?} accepted with {:?}", kind, mutation);
+        }
+    }
+
+    #[test]
+    fn test_deposit_rejects_mutated_witnesses() {
+        assert_mutations_rejected(make_deposit_proof::<F, C, D>, TransactionKind::Deposit);
+    }
+
+    #[test]
+    fn test_withdraw_rejects_mutated_witnesses() {
+        assert_mutations_rejected(make_withdraw_proof::<F, C, D>, TransactionKind::Withdraw);
+    }
+
+}
@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Result;
+use plonky2::field::extension::Extendable;
+use plonky2::fri::reduction_strategies::FriReductionStrategy;
+use plonky2::fri::FriConfig;
+use plonky2::gates::noop::NoopGate;
+use plonky2::hash::hash_types::RichField;
+use plonky2::iop::witness::{PartialWitness, WitnessWrite};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::circuit_data::{CircuitConfig, CommonCircuitData, VerifierOnlyCircuitData};
+use plonky2::plonk::config::{GenericConfig, Hasher};
+use plonky2::plonk::proof::ProofWithPublicInputs;
+
+// Circuits and configs shared by the tests.
+
+/// A high-rate recursive config, designed to be verifiable with fewer routed wires.
+pub fn high_rate_config() -> CircuitConfig {
+    let standard_config = CircuitConfig::standard_recursion_config();
+    CircuitConfig {
+        fri_config: FriConfig {
+            rate_bits: 7,
+            proof_of_work_bits: 16,
+            num_query_rounds: 12,
+            ..standard_config.fri_config.clone()
+        },
+        ..standard_config
+    }
+}
+
+/// The final config, optimized for the size of the Circom verifier.
+pub fn final_config() -> CircuitConfig {
+    CircuitConfig {
+        num_routed_wires: 37,
+        fri_config: FriConfig {
+            rate_bits: 8,
+            cap_height: 0,
+            proof_of_work_bits: 20,
+            reduction_strategy: FriReductionStrategy::MinSize(None),
+            num_query_rounds: 10,
+        },
+        ..high_rate_config()
+    }
+}
+
+/// Creates a dummy proof which should have roughly `num_dummy_gates` gates. Public input `i` is
+/// set to `i`.
+pub fn dummy_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
+    config: &CircuitConfig,
+    num_dummy_gates: u64,
+    num_public_inputs: u64,
+) -> Result<(
+    ProofWithPublicInputs<F, C, D>,
+    VerifierOnlyCircuitData<C, D>,
+    CommonCircuitData<F, D>,
+)>
+where
+    [(); C::Hasher::HASH_SIZE]:,
+{
+    let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+    for _ in 0..num_dummy_gates {
+        builder.add_gate(NoopGate, vec![]);
+    }
+    let pi = builder.add_virtual_targets(num_public_inputs as usize);
+    builder.register_public_inputs(&pi);
+
+    let data = builder.build::<C>();
+    let mut inputs = PartialWitness::new();
+    for (i, t) in pi.into_iter().enumerate() {
+        inputs.set_target(t, F::from_canonical_u64(i as u64));
+    }
+    let proof = data.prove(inputs)?;
+    data.verify(proof.clone())?;
+
+    Ok((proof, data.verifier_only, data.common))
+}
+
+/// A new directory under the system temp dir, so tests running in parallel do not share files.
+pub fn unique_temp_dir() -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "plonky2-circom-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,244 @@
+use anyhow::anyhow;
+use anyhow::Ok;
+use anyhow::Result;
+use plonky2::field::extension::Extendable;
+use plonky2::field::types::Field;
+use plonky2::hash::hash_types::HashOutTarget;
+use plonky2::hash::hash_types::MerkleCapTarget;
+use plonky2::hash::hash_types::RichField;
+use plonky2::hash::merkle_proofs::MerkleProofTarget;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::BoolTarget;
+use plonky2::iop::target::Target;
+use plonky2::iop::witness::PartialWitness;
+use plonky2::iop::witness::WitnessWrite;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::circuit_data::CircuitConfig;
+use plonky2::plonk::config::GenericConfig;
+use plonky2::plonk::config::Hasher;
+use crate::types::BALANCE_BITS;
+use crate::types::ProofTuple;
+use crate::types::Transaction;
+use crate::types::TransactionKind;
+
+
+#[derive(Debug, Clone)]
+pub struct TransactionTargets {
+    pub root_before_target: HashOutTarget,
+    pub root_after_target: HashOutTarget,
+    pub is_withdraw_target: BoolTarget,
+    pub merkle_proof_target: MerkleProofTarget,
+    pub private_key_target: Vec<Target>,
+    pub pubkey_target: HashOutTarget,
+    pub balance_before_target: Target,
+    pub nonce_target: Target,
+    pub position_index_target: Target,
+    pub amount_target: Target,
+}
+
+/// Deposit or withdrawal, selected by `is_withdraw_target`, so both share one verifier key.
+/// A deposit behaves as `deposit_circuit` and a withdrawal as `withdraw_circuit`. The public
+/// inputs are the roots, the transaction type and the owner's signature, which is zero for
+/// deposits.
+pub fn transaction_circuit<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> , const D: usize>(tree_height: usize, builder: &mut CircuitBuilder<F, D>) -> TransactionTargets {
+    let root_before_target = builder.add_virtual_hash();
+
+    let root_after_target = builder.add_virtual_hash();
+
+    let is_withdraw_target = builder.add_virtual_bool_target_safe();
+
+    let merkle_proof_target = MerkleProofTarget {
+        siblings: builder.add_virtual_hashes(tree_height),
+    };
+
+    // A deposit takes the public key from the witness, a withdrawal derives it from the
+    // private key.
+    let private_key_target = builder.add_virtual_targets(4);
+    let owner_pubkey_target = builder.hash_n_to_hash_no_pad::<PoseidonHash>(private_key_target.clone());
+    let pubkey_target = builder.add_virtual_hash();
+    let checked_pubkey_target = HashOutTarget {
+        elements: [0, 1, 2, 3].map(|i| {
+            builder.select(is_withdraw_target, owner_pubkey_target.elements[i], pubkey_target.elements[i])
+        }),
+    };
+    let balance_before_target = builder.add_virtual_target();
+    let nonce_target = builder.add_virtual_target();
+
+    let position_index_target = builder.add_virtual_target();
+    let position_index_bits = builder.split_le(position_index_target, tree_height);
+
+    builder.verify_merkle_proof_to_cap::<PoseidonHash>(
+        [checked_pubkey_target.elements.to_vec(), vec![balance_before_target, nonce_target]].concat(),
+        &position_index_bits,
+        &MerkleCapTarget(vec![root_before_target]),
+        &merkle_proof_target,
+    );
+
+    builder.range_check(balance_before_target, BALANCE_BITS);
+
+    let amount_target = builder.add_virtual_target();
+    builder.range_check(amount_target, BALANCE_BITS);
+    let credited_target = builder.add(balance_before_target, amount_target);
+    let debited_target = builder.sub(balance_before_target, amount_target);
+    let balance_after_target = builder.select(is_withdraw_target, debited_target, credited_target);
+    builder.range_check(balance_after_target, BALANCE_BITS);
+
+    // Only withdrawals bump the nonce.
+    let nonce_after_target = builder.add(nonce_target, is_withdraw_target.target);
+
+    builder.verify_merkle_proof_to_cap::<PoseidonHash>(
+        [checked_pubkey_target.elements.to_vec(), vec![balance_after_target, nonce_after_target]].concat(),
+        &position_index_bits,
+        &MerkleCapTarget(vec![root_after_target]),
+        &merkle_proof_target,
+    );
+
+    builder.register_public_inputs(&root_before_target.elements);
+    builder.register_public_inputs(&root_after_target.elements);
+    builder.register_public_input(is_withdraw_target.target);
+
+    let signature_target = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+        [
+            private_key_target.clone(),
+            vec![position_index_target, amount_target, nonce_target],
+        ]
+        .concat(),
+    );
+    let zero = builder.zero();
+    for h in signature_target.elements {
+        let s = builder.select(is_withdraw_target, h, zero);
+        builder.register_public_input(s);
+    }
+
+    TransactionTargets {
+        root_before_target,
+        root_after_target,
+        is_withdraw_target,
+        merkle_proof_target,
+        private_key_target,
+        pubkey_target,
+        balance_before_target,
+        nonce_target,
+        position_index_target,
+        amount_target,
+    }
+}
+
+pub fn make_transaction_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
+    config: &CircuitConfig,
+    tx: Transaction<F,D>
+) ->Result<ProofTuple<F,C,D>>
+    where
+    [(); C::Hasher::HASH_SIZE]:,
+{
+
+    let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+    let transaction_targets = transaction_circuit::<F, C, D>(tx.position_tree_depth, &mut builder);
+    let TransactionTargets {
+        root_before_target,
+        root_after_target,
+        is_withdraw_target,
+        merkle_proof_target,
+        private_key_target,
+        pubkey_target,
+        balance_before_target,
+        nonce_target,
+        position_index_target,
+        amount_target,
+    } = transaction_targets;
+
+    let Transaction {
+        transaction_type,
+        root_before,
+        root_after,
+        merkle_proof,
+        pubkey,
+        balance_before,
+        nonce,
+        position_index,
+        amount,
+        private_key,
+        ..
+    } = tx;
+    let private_key = match transaction_type {
+        TransactionKind::Deposit => private_key.unwrap_or([F::ZERO; 4]),
+        TransactionKind::Withdraw => private_key.ok_or_else(|| anyhow!("withdrawal without the owner's private key"))?,
+        _ => return Err(anyhow!("transaction_circuit only handles deposits and withdrawals")),
+    };
+
+    let mut pw: PartialWitness<F> = PartialWitness::new();
+    pw.set_hash_target(root_before_target, root_before);
+    pw.set_hash_target(root_after_target, root_after);
+    pw.set_bool_target(is_withdraw_target, transaction_type == TransactionKind::Withdraw);
+
+    for (t, k) in private_key_target.into_iter().zip(private_key) {
+        pw.set_target(t, k);
+    }
+    pw.set_hash_target(pubkey_target, pubkey);
+    pw.set_target(balance_before_target, balance_before);
+    pw.set_target(nonce_target, nonce);
+    pw.set_target(position_index_target, F::from_canonical_usize(position_index));
+    pw.set_target(amount_target, amount);
+
+    for (ht, h) in merkle_proof_target
+    .siblings
+    .into_iter()
+    .zip(merkle_proof.siblings)
+    {
+        pw.set_hash_target(ht, h);
+    }
+    let data = builder.build::<C>();
+
+    let proof = data.prove(pw)?;
+    data.verify(proof.clone())?;
+    Ok((proof, data.verifier_only, data.common))
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{types::{verify_proof, withdraw_signature}, sequencer::generate_transactions};
+    use crate::types::{F, C, D};
+    use super::*;
+
+
+    #[test]
+    fn test_deposit_and_withdraw_share_verifier_key() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let txs = generate_transactions::<F, D>(vec![TransactionKind::Deposit, TransactionKind::Withdraw]).unwrap();
+        let signature = withdraw_signature(
+            txs[1].private_key.unwrap(),
+            txs[1].position_index,
+            txs[1].amount,
+            txs[1].nonce,
+        );
+
+        let (deposit_pi, deposit_vd, deposit_cd) =
+            make_transaction_proof::<F, C, D>(&standard_config, txs[0].clone()).unwrap();
+        let (withdraw_pi, withdraw_vd, withdraw_cd) =
+            make_transaction_proof::<F, C, D>(&standard_config, txs[1].clone()).unwrap();
+
+        assert_eq!(deposit_vd.circuit_digest, withdraw_vd.circuit_digest);
+        assert_eq!(deposit_pi.public_inputs[8], F::ZERO);
+        assert_eq!(deposit_pi.public_inputs[9..13], [F::ZERO; 4]);
+        assert_eq!(withdraw_pi.public_inputs[8], F::ONE);
+        assert_eq!(withdraw_pi.public_inputs[9..13], signature.elements);
+
+        verify_proof(deposit_pi, deposit_vd, deposit_cd).unwrap();
+        verify_proof(withdraw_pi, withdraw_vd, withdraw_cd).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_deposit_cannot_be_proven_as_withdrawal() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let mut tx = generate_transactions::<F, D>(vec![TransactionKind::Deposit]).unwrap()[0].clone();
+        tx.transaction_type = TransactionKind::Withdraw;
+        tx.private_key = Some([F::ONE; 4]);
+        make_transaction_proof::<F, C, D>(&standard_config, tx).unwrap();
+    }
+
+
+}
@@ -0,0 +1,315 @@
+use anyhow::anyhow;
+use anyhow::ensure;
+use anyhow::Ok;
+use anyhow::Result;
+use plonky2::field::extension::Extendable;
+use plonky2::field::types::Field;
+use plonky2::hash::hash_types::HashOutTarget;
+use plonky2::hash::hash_types::MerkleCapTarget;
+use plonky2::hash::hash_types::RichField;
+use plonky2::hash::merkle_proofs::MerkleProofTarget;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::Target;
+use plonky2::iop::witness::PartialWitness;
+use plonky2::iop::witness::WitnessWrite;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::circuit_data::CircuitConfig;
+use plonky2::plonk::config::GenericConfig;
+use plonky2::plonk::config::Hasher;
+use plonky2::plonk::proof::ProofWithPublicInputs;
+use crate::types::BALANCE_BITS;
+use crate::types::ProofTuple;
+use crate::types::Transaction;
+
+
+#[derive(Debug, Clone)]
+pub struct TransferTargets {
+    pub root_before_target: HashOutTarget,
+    pub root_intermediate_target: HashOutTarget,
+    pub root_after_target: HashOutTarget,
+    pub amount_target: Target,
+    // sender
+    pub from_merkle_proof_target: MerkleProofTarget,
+    pub from_private_key_target: Vec<Target>,
+    pub from_balance_before_target: Target,
+    pub from_nonce_target: Target,
+    pub from_position_index_target: Target,
+    // receiver
+    pub to_merkle_proof_target: MerkleProofTarget,
+    pub to_pubkey_target: HashOutTarget,
+    pub to_balance_before_target: Target,
+    pub to_nonce_target: Target,
+    pub to_position_index_target: Target,
+}
+
+/// Moves `amount` from one position to another. The sender is debited first, taking the tree
+/// from `root_before` to `root_intermediate`, then the receiver is credited, taking it to
+/// `root_after`. Both sides use the same amount, so the total balance is conserved.
+pub fn transfer_circuit<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> , const D: usize>(tree_height: usize, builder: &mut CircuitBuilder<F, D>) -> TransferTargets {
+    let root_before_target = builder.add_virtual_hash();
+    let root_intermediate_target = builder.add_virtual_hash();
+    let root_after_target = builder.add_virtual_hash();
+
+    let amount_target = builder.add_virtual_target();
+    builder.range_check(amount_target, BALANCE_BITS);
+
+    // Debit the sender, who must own the position, as in `withdraw_circuit`.
+    let from_merkle_proof_target = MerkleProofTarget {
+        siblings: builder.add_virtual_hashes(tree_height),
+    };
+    let from_private_key_target = builder.add_virtual_targets(4);
+    let from_pubkey_target = builder.hash_n_to_hash_no_pad::<PoseidonHash>(from_private_key_target.clone());
+    let from_balance_before_target = builder.add_virtual_target();
+    let from_nonce_target = builder.add_virtual_target();
+    let from_position_index_target = builder.add_virtual_target();
+    let from_position_index_bits = builder.split_le(from_position_index_target, tree_height);
+
+    builder.verify_merkle_proof_to_cap::<PoseidonHash>(
+        [from_pubkey_target.elements.to_vec(), vec![from_balance_before_target, from_nonce_target]].concat(),
+        &from_position_index_bits,
+        &MerkleCapTarget(vec![root_before_target]),
+        &from_merkle_proof_target,
+    );
+
+    builder.range_check(from_balance_before_target, BALANCE_BITS);
+    let from_balance_after_target = builder.sub(from_balance_before_target, amount_target);
+    builder.range_check(from_balance_after_target, BALANCE_BITS);
+    let one = builder.one();
+    let from_nonce_after_target = builder.add(from_nonce_target, one);
+
+    builder.verify_merkle_proof_to_cap::<PoseidonHash>(
+        [from_pubkey_target.elements.to_vec(), vec![from_balance_after_target, from_nonce_after_target]].concat(),
+        &from_position_index_bits,
+        &MerkleCapTarget(vec![root_intermediate_target]),
+        &from_merkle_proof_target,
+    );
+
+    // Credit the receiver against the intermediate root, as in `deposit_circuit`.
+    let to_merkle_proof_target = MerkleProofTarget {
+        siblings: builder.add_virtual_hashes(tree_height),
+    };
+    let to_pubkey_target = builder.add_virtual_hash();
+    let to_balance_before_target = builder.add_virtual_target();
+    let to_nonce_target = builder.add_virtual_target();
+    let to_position_index_target = builder.add_virtual_target();
+    let to_position_index_bits = builder.split_le(to_position_index_target, tree_height);
+
+    builder.verify_merkle_proof_to_cap::<PoseidonHash>(
+        [to_pubkey_target.elements.to_vec(), vec![to_balance_before_target, to_nonce_target]].concat(),
+        &to_position_index_bits,
+        &MerkleCapTarget(vec![root_intermediate_target]),
+        &to_merkle_proof_target,
+    );
+
+    builder.range_check(to_balance_before_target, BALANCE_BITS);
+    let to_balance_after_target = builder.add(to_balance_before_target, amount_target);
+    builder.range_check(to_balance_after_target, BALANCE_BITS);
+
+    builder.verify_merkle_proof_to_cap::<PoseidonHash>(
+        [to_pubkey_target.elements.to_vec(), vec![to_balance_after_target, to_nonce_target]].concat(),
+        &to_position_index_bits,
+        &MerkleCapTarget(vec![root_after_target]),
+        &to_merkle_proof_target,
+    );
+
+    builder.register_public_inputs(&root_before_target.elements);
+    builder.register_public_inputs(&root_after_target.elements);
+
+    // The sender's signature over (index, amount, nonce, receiver), see `types::transfer_signature`.
+    let signature_target = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+        [
+            from_private_key_target.clone(),
+            vec![from_position_index_target, amount_target, from_nonce_target, to_position_index_target],
+        ]
+        .concat(),
+    );
+    builder.register_public_inputs(&signature_target.elements);
+
+    TransferTargets {
+        root_before_target,
+        root_intermediate_target,
+        root_after_target,
+        amount_target,
+        from_merkle_proof_target,
+        from_private_key_target,
+        from_balance_before_target,
+        from_nonce_target,
+        from_position_index_target,
+        to_merkle_proof_target,
+        to_pubkey_target,
+        to_balance_before_target,
+        to_nonce_target,
+        to_position_index_target,
+    }
+}
+
+/// Proves a transfer. Only the owner of the sending position can run this, as the witness
+/// includes their private key; the sequencer checks the result with `check_transfer_proof`.
+pub fn make_transfer_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
+    config: &CircuitConfig,
+    transfer_tx: Transaction<F,D>
+) ->Result<ProofTuple<F,C,D>>
+    where
+    [(); C::Hasher::HASH_SIZE]:,
+{
+
+    let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+    let transfer_targets = transfer_circuit::<F, C, D>(transfer_tx.position_tree_depth, &mut builder);
+    let TransferTargets {
+        root_before_target,
+        root_intermediate_target,
+        root_after_target,
+        amount_target,
+        from_merkle_proof_target,
+        from_private_key_target,
+        from_balance_before_target,
+        from_nonce_target,
+        from_position_index_target,
+        to_merkle_proof_target,
+        to_pubkey_target,
+        to_balance_before_target,
+        to_nonce_target,
+        to_position_index_target,
+    } = transfer_targets;
+
+    let Transaction {
+        root_before,
+        root_after,
+        merkle_proof,
+        balance_before,
+        nonce,
+        position_index,
+        amount,
+        private_key,
+        transfer_to,
+        ..
+    } = transfer_tx;
+    let private_key = private_key.ok_or_else(|| anyhow!("transfer without the sender's private key"))?;
+    let to = transfer_to.ok_or_else(|| anyhow!("transfer without a receiver"))?;
+
+    let mut pw: PartialWitness<F> = PartialWitness::new();
+    pw.set_hash_target(root_before_target, root_before);
+    pw.set_hash_target(root_intermediate_target, to.root_intermediate);
+    pw.set_hash_target(root_after_target, root_after);
+    pw.set_target(amount_target, amount);
+
+    for (t, k) in from_private_key_target.into_iter().zip(private_key) {
+        pw.set_target(t, k);
+    }
+    pw.set_target(from_balance_before_target, balance_before);
+    pw.set_target(from_nonce_target, nonce);
+    pw.set_target(from_position_index_target, F::from_canonical_usize(position_index));
+    for (ht, h) in from_merkle_proof_target
+    .siblings
+    .into_iter()
+    .zip(merkle_proof.siblings)
+    {
+        pw.set_hash_target(ht, h);
+    }
+
+    pw.set_hash_target(to_pubkey_target, to.pubkey);
+    pw.set_target(to_balance_before_target, to.balance_before);
+    pw.set_target(to_nonce_target, to.nonce);
+    pw.set_target(to_position_index_target, F::from_canonical_usize(to.position_index));
+    for (ht, h) in to_merkle_proof_target
+    .siblings
+    .into_iter()
+    .zip(to.merkle_proof.siblings)
+    {
+        pw.set_hash_target(ht, h);
+    }
+
+    let data = builder.build::<C>();
+
+    let proof = data.prove(pw)?;
+    data.verify(proof.clone())?;
+    Ok((proof, data.verifier_only, data.common))
+}
+
+/// Checks a transfer proven by the sender with `make_transfer_proof`, like
+/// `withdraw::check_withdraw_proof`. The intermediate root is not checked, as `root_after` already
+/// fixes the whole tree.
+pub fn check_transfer_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
+    config: &CircuitConfig,
+    transfer_tx: &Transaction<F, D>,
+    proof: ProofWithPublicInputs<F, C, D>,
+) -> Result<ProofTuple<F, C, D>>
+    where
+    [(); C::Hasher::HASH_SIZE]:,
+{
+    let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+    transfer_circuit::<F, C, D>(transfer_tx.position_tree_depth, &mut builder);
+    let data = builder.build::<C>();
+
+    data.verify(proof.clone())?;
+    ensure!(
+        proof.public_inputs[0..4] == transfer_tx.root_before.elements,
+        "transfer proven against another root"
+    );
+    ensure!(
+        proof.public_inputs[4..8] == transfer_tx.root_after.elements,
+        "transfer proven with another amount, nonce or receiver"
+    );
+    Ok((proof, data.verifier_only, data.common))
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{types::verify_proof, sequencer::generate_transactions};
+    use crate::types::{TransactionKind, F, C, D, POSITION_TREE_DEPTH, transfer_signature};
+    use super::*;
+
+
+    #[test]
+    fn test_transfer_circuit() {
+        let config = CircuitConfig::standard_recursion_zk_config();
+        let mut builder = CircuitBuilder::<F, D>::new(config);
+        transfer_circuit::<F,C,D>(POSITION_TREE_DEPTH, &mut builder);
+    }
+
+    #[test]
+    fn test_transfer_proof() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let transfer_tx = generate_transactions::<F, D>(vec![TransactionKind::Transfer]).unwrap()[0].clone();
+        let to = transfer_tx.transfer_to.clone().unwrap();
+        let signature = transfer_signature(
+            transfer_tx.private_key.unwrap(),
+            transfer_tx.position_index,
+            transfer_tx.amount,
+            transfer_tx.nonce,
+            to.position_index,
+        );
+        let (pi, vd, cd) =
+            make_transfer_proof::<F, C, D>(&standard_config, transfer_tx).unwrap();
+        assert_eq!(pi.public_inputs[8..12], signature.elements);
+        verify_proof(pi, vd, cd).unwrap();
+    }
+
+    #[test]
+    fn test_check_transfer_proof() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let transfer_tx = generate_transactions::<F, D>(vec![TransactionKind::Transfer]).unwrap()[0].clone();
+        let (pi, _, _) =
+            make_transfer_proof::<F, C, D>(&standard_config, transfer_tx.clone()).unwrap();
+
+        let unsigned_tx = Transaction { private_key: None, ..transfer_tx };
+        check_transfer_proof::<F, C, D>(&standard_config, &unsigned_tx, pi.clone()).unwrap();
+        let other_tx = Transaction { root_before: unsigned_tx.root_after, ..unsigned_tx.clone() };
+        assert!(check_transfer_proof::<F, C, D>(&standard_config, &other_tx, pi).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_transfer_with_wrong_amount() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let mut transfer_tx = generate_transactions::<F, D>(vec![TransactionKind::Transfer]).unwrap()[0].clone();
+        // The roots were computed for the original amount.
+        transfer_tx.amount += F::ONE;
+        make_transfer_proof::<F, C, D>(&standard_config, transfer_tx).unwrap();
+    }
+
+
+}
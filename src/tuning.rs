@@ -0,0 +1,168 @@
+use anyhow::{anyhow, Result};
+use log::info;
+use plonky2::field::extension::Extendable;
+use plonky2::fri::reduction_strategies::FriReductionStrategy;
+use plonky2::fri::FriConfig;
+use plonky2::hash::hash_types::RichField;
+use plonky2::plonk::circuit_data::{CircuitConfig, CommonCircuitData, VerifierOnlyCircuitData};
+use plonky2::plonk::config::{AlgebraicHasher, GenericConfig, Hasher};
+use plonky2::plonk::proof::ProofWithPublicInputs;
+
+use crate::estimator::estimate_circom_constraints;
+use crate::recursive::recursive_proof;
+use crate::security::{conjectured_fri_bits, SecurityLevel};
+use crate::verifier::generate_verifier_config;
+
+pub struct FinalConfigCandidate {
+    pub config: CircuitConfig,
+    pub security_bits: usize,
+    pub estimated_constraints: usize,
+}
+
+/// Candidate final layer configs derived from `base`, each with just enough query rounds to
+/// reach `target_security_bits`.
+pub fn final_config_candidates(
+    base: &CircuitConfig,
+    target_security_bits: usize,
+) -> Vec<CircuitConfig> {
+    let mut candidates = Vec::new();
+    for rate_bits in 3..=8 {
+        for proof_of_work_bits in [16, 20, 24] {
+            let remaining_bits = target_security_bits.saturating_sub(proof_of_work_bits as usize);
+            let num_query_rounds = (remaining_bits + rate_bits - 1) / rate_bits;
+            candidates.push(CircuitConfig {
+                security_bits: target_security_bits,
+                fri_config: FriConfig {
+                    rate_bits,
+                    cap_height: 0,
+                    proof_of_work_bits,
+                    reduction_strategy: FriReductionStrategy::MinSize(None),
+                    num_query_rounds,
+                },
+                ..base.clone()
+            });
+        }
+    }
+    candidates
+}
+
+/// Wraps the inner proof with every candidate config and returns the one with the smallest
+/// estimated Circom verifier whose conjectured `SecurityLevel` reaches `target_security_bits`.
+/// The Circom verifier fixes `num_challenges` to 2, so the base field challenges cap the level
+/// below what the FRI part alone would give.
+pub fn search_final_config<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    InnerC: GenericConfig<D, F = F>,
+    const D: usize,
+>(
+    inner_proof: &ProofWithPublicInputs<F, InnerC, D>,
+    inner_vd: &VerifierOnlyCircuitData<InnerC, D>,
+    inner_cd: &CommonCircuitData<F, D>,
+    candidates: Vec<CircuitConfig>,
+    target_security_bits: usize,
+) -> Result<FinalConfigCandidate>
+where
+    InnerC::Hasher: AlgebraicHasher<F>,
+    [(); C::Hasher::HASH_SIZE]:,
+{
+    let mut best: Option<FinalConfigCandidate> = None;
+    for config in candidates {
+        // The FRI part bounds the whole level, so it rules candidates out before proving.
+        if conjectured_fri_bits(&config.fri_config) < target_security_bits {
+            continue;
+        }
+
+        let (proof, _, common) = recursive_proof::<F, C, InnerC, D>(
+            inner_proof.clone(),
+            inner_vd.clone(),
+            inner_cd.clone(),
+            None,
+            &config,
+            None,
+        )?;
+        let level = SecurityLevel::from_common(&common);
+        if level.conjectured_bits() < target_security_bits as f64 {
+            continue;
+        }
+        // The Circom verifier only supports two FRI reduction steps, and needs a query round.
+        match proof.proof.opening_proof.query_round_proofs.first() {
+            Some(round) if round.steps.len() == 2 => {}
+            _ => continue,
+        }
+
+        let conf = generate_verifier_config(&proof)?;
+        let estimated_constraints = estimate_circom_constraints(&conf, &common).total();
+        info!(
+            "rate_bits: {}, num_query_rounds: {}, proof_of_work_bits: {}, estimated constraints: {}, \
+             security bits: {:.1} conjectured, {:.1} proven",
+            config.fri_config.rate_bits,
+            config.fri_config.num_query_rounds,
+            config.fri_config.proof_of_work_bits,
+            estimated_constraints,
+            level.conjectured_bits(),
+            level.proven_bits()
+        );
+        if best
+            .as_ref()
+            .map_or(true, |b| estimated_constraints < b.estimated_constraints)
+        {
+            best = Some(FinalConfigCandidate {
+                config,
+                security_bits: level.conjectured_bits() as usize,
+                estimated_constraints,
+            });
+        }
+    }
+
+    best.ok_or_else(|| {
+        anyhow!(
+            "no candidate config reaches {} bits of security",
+            target_security_bits
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::plonk::circuit_data::CircuitConfig;
+
+    use crate::deposit::make_deposit_proof;
+    use crate::sequencer::generate_transactions;
+    use crate::types::{Cbn128, TransactionKind, C, D, F};
+
+    use super::*;
+
+    #[test]
+    fn test_final_config_candidates() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        for config in final_config_candidates(&standard_config, 100) {
+            assert!(conjectured_fri_bits(&config.fri_config) >= 100);
+            assert_eq!(config.fri_config.cap_height, 0);
+        }
+    }
+
+    #[test]
+    fn test_search_final_config() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let deposit_tx = generate_transactions::<F, D>(vec![TransactionKind::Deposit]).unwrap();
+        let (pi, vd, cd) =
+            make_deposit_proof::<F, C, D>(&standard_config, deposit_tx[0].clone()).unwrap();
+
+        // Two challenges over the wrapping circuit's domain give less than 100 bits.
+        let candidates = final_config_candidates(&standard_config, 100)
+            .into_iter()
+            .filter(|c| c.fri_config.rate_bits >= 7)
+            .collect::<Vec<_>>();
+        assert!(
+            search_final_config::<F, Cbn128, C, D>(&pi, &vd, &cd, candidates.clone(), 100).is_err()
+        );
+
+        let candidates = final_config_candidates(&standard_config, 85)
+            .into_iter()
+            .filter(|c| c.fri_config.rate_bits >= 7)
+            .collect();
+        let best = search_final_config::<F, Cbn128, C, D>(&pi, &vd, &cd, candidates, 85).unwrap();
+        assert!(best.security_bits >= 85);
+    }
+}
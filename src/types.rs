@@ -9,7 +9,11 @@ use plonky2::{
         }, field::{goldilocks_field::GoldilocksField, extension::Extendable}
     };
 use plonky2::plonk::config::Hasher;
+use plonky2::field::types::Field;
+use std::time::Instant;
+
 use anyhow::Result;
+use log::info;
 use serde::{Deserialize, Serialize};
 
 use crate::config::PoseidonBN128GoldilocksConfig;
@@ -17,11 +21,25 @@ use crate::config::PoseidonBN128GoldilocksConfig;
 
 pub const D: usize = 2;
 pub const POSITION_TREE_DEPTH: usize = 10;
-pub const DEPOSIT: usize = 0;
-pub const WITHDRAW: usize = 1;
-pub const RECURSIVE0: usize = 2;
-pub const RECURSIVE1: usize = 3;
-pub const ILLEGAL_DEPOSIT: usize = 4;
+/// Balances and amounts are range checked to this many bits. At most 62, so that the sum of two
+/// of them cannot wrap around the field order, and a difference that goes negative wraps to
+/// above `2^BALANCE_BITS`.
+pub const BALANCE_BITS: usize = 62;
+
+/// The kind of a transaction. The discriminants are the codes hashed into batch commitments.
+/// Only the kinds clients submit are serialized.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransactionKind {
+    Deposit = 0,
+    Withdraw = 1,
+    #[serde(skip)]
+    Recursive0 = 2,
+    #[serde(skip)]
+    Recursive1 = 3,
+    #[serde(skip)]
+    IllegalDeposit = 4,
+    Transfer = 5,
+}
 
 
 // pub type C = PoseidonBN128GoldilocksConfig;
@@ -40,26 +58,50 @@ pub type ProofTuple<F, C, const D: usize> = (
     // Option<VDProof>,
 );
 
+/// Membership proof of a verifier key in the tree of allowlisted verifier keys.
 #[derive(Deserialize, Serialize, Debug, Clone)]
-pub struct VDProof {
-    pub merkle_proof: MerkleProof<GoldilocksField,PoseidonHash>,
-    pub index: GoldilocksField,
-    pub root: HashOut<GoldilocksField>,
+#[serde(bound = "")]
+pub struct VDProof<F: RichField> {
+    pub merkle_proof: MerkleProof<F, PoseidonHash>,
+    pub index: F,
+    pub root: HashOut<F>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(bound = "")]
 pub struct Transaction<F: RichField+ Extendable<D>, const D: usize> {
-    pub transaction_type: usize,
+    pub transaction_type: TransactionKind,
     pub root_before: HashOut<F>,
     pub root_after: HashOut<F>,
     pub merkle_proof: MerkleProof<F, PoseidonHash>,
+    pub pubkey: HashOut<F>,
     pub balance_before: F,
+    pub nonce: F,
     pub position_index: usize,
     pub amount: F,
     pub position_tree_depth: usize,
+    // Only withdrawals and transfers need the owner's key. It stays with the owner, so it is
+    // never serialized.
+    #[serde(skip)]
+    pub private_key: Option<[F; 4]>,
+    // Only transfers touch a second position.
+    pub transfer_to: Option<TransferReceiver<F>>,
     // pub vd_deposit_proof: VDProof,
 }
 
+/// The receiving side of a transfer. Its Merkle proof is against `root_intermediate`, the root
+/// after the sender has been debited.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(bound = "")]
+pub struct TransferReceiver<F: RichField> {
+    pub root_intermediate: HashOut<F>,
+    pub merkle_proof: MerkleProof<F, PoseidonHash>,
+    pub pubkey: HashOut<F>,
+    pub balance_before: F,
+    pub nonce: F,
+    pub position_index: usize,
+}
+
 
 // #[derive(Debug, Clone)]
 // pub struct PositionSet<F: RichField, C: GenericConfig<D, F = F> >(pub MerkleTree<F, C::Hasher>);
@@ -80,14 +122,144 @@ pub fn verify_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, co
         common: commom,
     };
 
+    let start = Instant::now();
     verifier_data.verify(proof)?;
-
-    println!("Proof verified");
+    info!("Proof verified in {:?}", start.elapsed());
     Ok(())
 }
 
 
-// pub struct Position{
-//     pub pubkey: Digest,
-//     pub balance: F,
-// }
\ No newline at end of file
+/// Witness of `insert_circuit`: a new position opened at an empty leaf.
+#[derive(Debug, Clone)]
+pub struct Insertion<F: RichField> {
+    pub root_before: HashOut<F>,
+    pub root_after: HashOut<F>,
+    pub merkle_proof: MerkleProof<F, PoseidonHash>,
+    pub pubkey: HashOut<F>,
+    pub position_index: usize,
+    pub position_tree_depth: usize,
+}
+
+/// A leaf of the position tree. The public key is the Poseidon hash of the owner's private key,
+/// and the nonce counts the owner's withdrawals.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(bound = "")]
+pub struct Position<F: RichField> {
+    pub pubkey: HashOut<F>,
+    pub balance: F,
+    pub nonce: F,
+}
+
+impl<F: RichField> Position<F> {
+    pub fn to_leaf(&self) -> Vec<F> {
+        let mut leaf = self.pubkey.elements.to_vec();
+        leaf.push(self.balance);
+        leaf.push(self.nonce);
+        leaf
+    }
+}
+
+pub fn public_key<F: RichField>(private_key: [F; 4]) -> HashOut<F> {
+    PoseidonHash::hash_no_pad(&private_key)
+}
+
+/// Hash-preimage signature of a withdrawal of `amount` from `position_index` at `nonce`. Only
+/// the holder of the private key can compute it, and the withdraw circuit recomputes it from the
+/// same private key it checks against the position's public key.
+pub fn withdraw_signature<F: RichField>(
+    private_key: [F; 4],
+    position_index: usize,
+    amount: F,
+    nonce: F,
+) -> HashOut<F> {
+    let mut inputs = private_key.to_vec();
+    inputs.push(F::from_canonical_usize(position_index));
+    inputs.push(amount);
+    inputs.push(nonce);
+    PoseidonHash::hash_no_pad(&inputs)
+}
+
+/// Like `withdraw_signature`, and also binds the receiving position.
+pub fn transfer_signature<F: RichField>(
+    private_key: [F; 4],
+    position_index: usize,
+    amount: F,
+    nonce: F,
+    to_position_index: usize,
+) -> HashOut<F> {
+    let mut inputs = private_key.to_vec();
+    inputs.push(F::from_canonical_usize(position_index));
+    inputs.push(amount);
+    inputs.push(nonce);
+    inputs.push(F::from_canonical_usize(to_position_index));
+    PoseidonHash::hash_no_pad(&inputs)
+}
+
+/// Transaction batches are exchanged as JSON with clients, and stored as bincode for replay.
+pub fn transactions_to_json<F: RichField + Extendable<D>, const D: usize>(
+    txs: &[Transaction<F, D>],
+) -> Result<String> {
+    Ok(serde_json::to_string(txs)?)
+}
+
+pub fn transactions_from_json<F: RichField + Extendable<D>, const D: usize>(
+    json: &str,
+) -> Result<Vec<Transaction<F, D>>> {
+    Ok(serde_json::from_str(json)?)
+}
+
+pub fn transactions_to_bincode<F: RichField + Extendable<D>, const D: usize>(
+    txs: &[Transaction<F, D>],
+) -> Result<Vec<u8>> {
+    Ok(bincode::serialize(txs)?)
+}
+
+pub fn transactions_from_bincode<F: RichField + Extendable<D>, const D: usize>(
+    bytes: &[u8],
+) -> Result<Vec<Transaction<F, D>>> {
+    Ok(bincode::deserialize(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sequencer::generate_transactions;
+    use crate::transfer::make_transfer_proof;
+    use plonky2::plonk::circuit_data::CircuitConfig;
+
+    use super::*;
+
+    #[test]
+    fn test_transaction_batch_round_trip() {
+        let txs = generate_transactions::<F, D>(vec![
+            TransactionKind::Deposit,
+            TransactionKind::Withdraw,
+            TransactionKind::Transfer,
+        ])
+        .unwrap();
+
+        let json = transactions_to_json(&txs).unwrap();
+        assert!(json.contains("\"Transfer\""));
+        let from_json = transactions_from_json::<F, D>(&json).unwrap();
+        let from_bincode =
+            transactions_from_bincode::<F, D>(&transactions_to_bincode(&txs).unwrap()).unwrap();
+
+        for replayed in [from_json, from_bincode] {
+            assert_eq!(replayed.len(), txs.len());
+            for (a, b) in replayed.iter().zip(txs.iter()) {
+                assert_eq!(a.transaction_type, b.transaction_type);
+                assert_eq!(a.root_before, b.root_before);
+                assert_eq!(a.root_after, b.root_after);
+                assert_eq!(a.private_key, None);
+            }
+        }
+        assert!(serde_json::to_string(&TransactionKind::IllegalDeposit).is_err());
+
+        // A replayed transaction is still a valid witness, once the owner attaches their key.
+        let transfer_tx = Transaction {
+            private_key: txs[2].private_key,
+            ..transactions_from_json::<F, D>(&json).unwrap()[2].clone()
+        };
+        make_transfer_proof::<F, C, D>(&CircuitConfig::standard_recursion_config(), transfer_tx)
+            .unwrap();
+    }
+}
@@ -1,7 +1,7 @@
 use std::fmt::Write;
 
 use anyhow::Result;
-use log::Level;
+use log::{info, Level};
 use plonky2::field::extension::{Extendable, FieldExtension};
 use plonky2::field::types::Field;
 use plonky2::gates::noop::NoopGate;
@@ -565,7 +565,7 @@ pub fn generate_proof_base64<
 
     let proof_bytes = pwpi.to_bytes();
     assert_eq!(proof_bytes.len(), proof_size);
-    println!("proof size: {}", proof_size);
+    info!("proof size: {}", proof_size);
 
     Ok(serde_json::to_string(&circom_proof).unwrap())
 }
@@ -581,7 +581,7 @@ pub fn generate_circom_verifier<
 ) -> anyhow::Result<(String, String)> {
     assert_eq!(F::BITS, 64);
     assert_eq!(F::Extension::BITS, 128);
-    println!("Generating Circom files ...");
+    info!("Generating Circom files ...");
 
     // Load template contract
     let mut constants = std::fs::read_to_string("./src/template_constants.circom")
@@ -938,8 +938,9 @@ mod tests {
         },
     };
 
+    use crate::recursive::recursive_proof;
     use crate::verifier::{
-        generate_circom_verifier, generate_proof_base64, generate_verifier_config, recursive_proof,
+        generate_circom_verifier, generate_proof_base64, generate_verifier_config,
     };
 
     /// Creates a dummy proof which should have roughly `num_dummy_gates` gates.
@@ -1010,7 +1011,7 @@ mod tests {
         let (proof, vd, cd) = dummy_proof::<F, C, D>(&final_config, 4_000, 0)?;
 
         let conf = generate_verifier_config(&proof)?;
-        let (circom_constants, circom_gates) = generate_circom_verifier(&conf, &cd, &vd)?;
+        let (circom_constants, circom_gates, _) = generate_circom_verifier(&conf, &cd, &vd, None)?;
 
         let mut circom_file = File::create("./circom/circuits/constants.circom")?;
         circom_file.write_all(circom_constants.as_bytes())?;
@@ -1041,7 +1042,7 @@ mod tests {
         let (proof, vd, cd) = dummy_proof::<F, C, D>(&standard_config, 4_000, 4)?;
 
         let conf = generate_verifier_config(&proof)?;
-        let (circom_constants, circom_gates) = generate_circom_verifier(&conf, &cd, &vd)?;
+        let (circom_constants, circom_gates, _) = generate_circom_verifier(&conf, &cd, &vd, None)?;
 
         let mut circom_file = File::create("./circom/circuits/constants.circom")?;
         circom_file.write_all(circom_constants.as_bytes())?;
@@ -1094,14 +1095,14 @@ mod tests {
 
         let (proof, vd, cd) = dummy_proof::<F, C, D>(&standard_config, 4_000, 4)?;
         let (proof, vd, cd) =
-            recursive_proof::<F, C, C, D>(proof, vd, cd, &high_rate_config, None, true, true)?;
+            recursive_proof::<F, C, C, D>(proof, vd, cd, None, &high_rate_config, None)?;
 
         type CBn128 = PoseidonBN128GoldilocksConfig;
         let (proof, vd, cd) =
-            recursive_proof::<F, CBn128, C, D>(proof, vd, cd, &final_config, None, true, true)?;
+            recursive_proof::<F, CBn128, C, D>(proof, vd, cd, None, &final_config, None)?;
 
         let conf = generate_verifier_config(&proof)?;
-        let (circom_constants, circom_gates) = generate_circom_verifier(&conf, &cd, &vd)?;
+        let (circom_constants, circom_gates, _) = generate_circom_verifier(&conf, &cd, &vd, None)?;
 
         let mut circom_file = File::create("./circom/circuits/constants.circom")?;
         circom_file.write_all(circom_constants.as_bytes())?;
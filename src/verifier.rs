@@ -1,23 +1,21 @@
 use std::fmt::Write;
+use std::time::Instant;
 
 use anyhow::Result;
-use log::Level;
+use log::{debug, info};
 use plonky2::field::extension::{Extendable, FieldExtension};
 use plonky2::field::types::Field;
-use plonky2::gates::noop::NoopGate;
 use plonky2::hash::hash_types::RichField;
-use plonky2::iop::witness::{PartialWitness, WitnessWrite};
-use plonky2::plonk::circuit_builder::CircuitBuilder;
-use plonky2::plonk::circuit_data::{
-    CircuitConfig, CommonCircuitData, VerifierCircuitTarget, VerifierOnlyCircuitData,
-};
+use plonky2::plonk::circuit_data::{CommonCircuitData, VerifierOnlyCircuitData};
 use plonky2::plonk::config::GenericHashOut;
-use plonky2::plonk::config::{AlgebraicHasher, GenericConfig, Hasher};
+use plonky2::plonk::config::GenericConfig;
 use plonky2::plonk::proof::ProofWithPublicInputs;
-use plonky2::plonk::prover::prove;
-use plonky2::util::timing::TimingTree;
 use plonky2_util::log2_strict;
-use serde::Serialize;
+use plonky2::fri::oracle::SALT_SIZE;
+use serde::{Deserialize, Serialize};
+
+use crate::security::SecurityLevel;
+use crate::stats::ProofStats;
 
 pub fn encode_hex(bytes: &[u8]) -> String {
     let mut s = String::with_capacity(bytes.len() * 2);
@@ -27,157 +25,107 @@ pub fn encode_hex(bytes: &[u8]) -> String {
     s
 }
 
-pub fn recursive_proof<
-    F: RichField + Extendable<D>,
-    C: GenericConfig<D, F = F>,
-    InnerC: GenericConfig<D, F = F>,
-    const D: usize,
->(
-    inner_proof: ProofWithPublicInputs<F, InnerC, D>,
-    inner_vd: VerifierOnlyCircuitData<InnerC, D>,
-    inner_cd: CommonCircuitData<F, D>,
-    config: &CircuitConfig,
-    min_degree_bits: Option<usize>,
-    print_gate_counts: bool,
-    print_timing: bool,
-) -> Result<(
-    ProofWithPublicInputs<F, C, D>,
-    VerifierOnlyCircuitData<C, D>,
-    CommonCircuitData<F, D>,
-)>
-where
-    InnerC::Hasher: AlgebraicHasher<F>,
-    [(); C::Hasher::HASH_SIZE]:,
-{
-    let mut builder = CircuitBuilder::<F, D>::new(config.clone());
-    let mut pw = PartialWitness::new();
-    let pt = builder.add_virtual_proof_with_pis::<InnerC>(&inner_cd);
-    pw.set_proof_with_pis_target(&pt, &inner_proof);
-
-    let inner_data = VerifierCircuitTarget {
-        constants_sigmas_cap: builder.add_virtual_cap(inner_cd.config.fri_config.cap_height),
-        circuit_digest: builder.add_virtual_hash(),
-    };
-    pw.set_cap_target(
-        &inner_data.constants_sigmas_cap,
-        &inner_vd.constants_sigmas_cap,
-    );
-    pw.set_hash_target(inner_data.circuit_digest, inner_vd.circuit_digest);
-
-    builder.register_public_inputs(inner_data.circuit_digest.elements.as_slice());
-    for i in 0..builder.config.fri_config.num_cap_elements() {
-        builder.register_public_inputs(&inner_data.constants_sigmas_cap.0[i].elements);
-    }
-    builder.verify_proof::<InnerC>(&pt, &inner_data, &inner_cd);
-
-    if print_gate_counts {
-        builder.print_gate_counts(0);
-    }
-
-    if let Some(min_degree_bits) = min_degree_bits {
-        // We don't want to pad all the way up to 2^min_degree_bits, as the builder will add a
-        // few special gates afterward. So just pad to 2^(min_degree_bits - 1) + 1. Then the
-        // builder will pad to the next power of two, 2^min_degree_bits.
-        let min_gates = (1 << (min_degree_bits - 1)) + 1;
-        for _ in builder.num_gates()..min_gates {
-            builder.add_gate(NoopGate, vec![]);
-        }
-    }
-
-    let data = builder.build::<C>();
-
-    let mut timing = TimingTree::new("prove", Level::Debug);
-    let proof = prove(&data.prover_only, &data.common, pw, &mut timing)?;
-    if print_timing {
-        timing.print();
-    }
-
-    println!("######################### recursive verify #########################");
-    data.verify(proof.clone())?;
-
-    Ok((proof, data.verifier_only, data.common))
-}
-
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct VerifierConfig {
-    hash_size: usize,
-    field_size: usize,
-    ext_field_size: usize,
-    merkle_height_size: usize,
+    pub hash_size: usize,
+    pub field_size: usize,
+    pub ext_field_size: usize,
+    pub merkle_height_size: usize,
 
-    num_wires_cap: usize,
-    num_plonk_zs_partial_products_cap: usize,
-    num_quotient_polys_cap: usize,
+    pub num_wires_cap: usize,
+    pub num_plonk_zs_partial_products_cap: usize,
+    pub num_quotient_polys_cap: usize,
 
     // openings
-    num_openings_constants: usize,
-    num_openings_plonk_sigmas: usize,
-    num_openings_wires: usize,
-    num_openings_plonk_zs: usize,
-    num_openings_plonk_zs_next: usize,
-    num_openings_partial_products: usize,
-    num_openings_quotient_polys: usize,
+    pub num_openings_constants: usize,
+    pub num_openings_plonk_sigmas: usize,
+    pub num_openings_wires: usize,
+    pub num_openings_plonk_zs: usize,
+    pub num_openings_plonk_zs_next: usize,
+    pub num_openings_partial_products: usize,
+    pub num_openings_quotient_polys: usize,
 
     // fri proof
     // .commit phase
-    num_fri_commit_round: usize,
-    fri_commit_merkle_cap_height: usize,
+    pub num_fri_commit_round: usize,
+    pub fri_commit_merkle_cap_height: usize,
     // .query round
-    num_fri_query_round: usize,
+    pub num_fri_query_round: usize,
     // ..init
-    num_fri_query_init_constants_sigmas_v: usize,
-    num_fri_query_init_constants_sigmas_p: usize,
-    num_fri_query_init_wires_v: usize,
-    num_fri_query_init_wires_p: usize,
-    num_fri_query_init_zs_partial_v: usize,
-    num_fri_query_init_zs_partial_p: usize,
-    num_fri_query_init_quotient_v: usize,
-    num_fri_query_init_quotient_p: usize,
+    pub num_fri_query_init_constants_sigmas_v: usize,
+    pub num_fri_query_init_constants_sigmas_p: usize,
+    pub num_fri_query_init_wires_v: usize,
+    pub num_fri_query_init_wires_p: usize,
+    pub num_fri_query_init_zs_partial_v: usize,
+    pub num_fri_query_init_zs_partial_p: usize,
+    pub num_fri_query_init_quotient_v: usize,
+    pub num_fri_query_init_quotient_p: usize,
     // ..steps
-    num_fri_query_step0_v: usize,
-    num_fri_query_step0_p: usize,
-    num_fri_query_step1_v: usize,
-    num_fri_query_step1_p: usize,
+    pub num_fri_query_step0_v: usize,
+    pub num_fri_query_step0_p: usize,
+    pub num_fri_query_step1_v: usize,
+    pub num_fri_query_step1_p: usize,
     // .final poly
-    num_fri_final_poly_ext_v: usize,
+    pub num_fri_final_poly_ext_v: usize,
     // public inputs
-    num_public_inputs: usize,
+    pub num_public_inputs: usize,
+    /// Packs three public inputs into each public signal of the Groth16 proof, which is cheaper
+    /// to verify on chain.
+    #[serde(default)]
+    pub compress_public_inputs: bool,
+}
+
+impl VerifierConfig {
+    pub fn public_inputs_per_signal(&self) -> usize {
+        if self.compress_public_inputs {
+            3
+        } else {
+            1
+        }
+    }
+
+    /// Number of public signals of the Groth16 proof of the Circom verifier.
+    pub fn num_public_signals(&self) -> usize {
+        let per_signal = self.public_inputs_per_signal();
+        (self.num_public_inputs + per_signal - 1) / per_signal
+    }
 }
 
-#[derive(Serialize)]
+/// A proof laid out as the inputs of the Circom verifier, one decimal string per field element.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct ProofForCircom {
-    wires_cap: Vec<Vec<String>>,
-    plonk_zs_partial_products_cap: Vec<Vec<String>>,
-    quotient_polys_cap: Vec<Vec<String>>,
-
-    openings_constants: Vec<Vec<String>>,
-    openings_plonk_sigmas: Vec<Vec<String>>,
-    openings_wires: Vec<Vec<String>>,
-    openings_plonk_zs: Vec<Vec<String>>,
-    openings_plonk_zs_next: Vec<Vec<String>>,
-    openings_partial_products: Vec<Vec<String>>,
-    openings_quotient_polys: Vec<Vec<String>>,
-
-    fri_commit_phase_merkle_caps: Vec<Vec<Vec<String>>>,
-
-    fri_query_init_constants_sigmas_v: Vec<Vec<String>>,
-    fri_query_init_constants_sigmas_p: Vec<Vec<Vec<String>>>,
-    fri_query_init_wires_v: Vec<Vec<String>>,
-    fri_query_init_wires_p: Vec<Vec<Vec<String>>>,
-    fri_query_init_zs_partial_v: Vec<Vec<String>>,
-    fri_query_init_zs_partial_p: Vec<Vec<Vec<String>>>,
-    fri_query_init_quotient_v: Vec<Vec<String>>,
-    fri_query_init_quotient_p: Vec<Vec<Vec<String>>>,
-
-    fri_query_step0_v: Vec<Vec<Vec<String>>>,
-    fri_query_step0_p: Vec<Vec<Vec<String>>>,
-    fri_query_step1_v: Vec<Vec<Vec<String>>>,
-    fri_query_step1_p: Vec<Vec<Vec<String>>>,
-
-    fri_final_poly_ext_v: Vec<Vec<String>>,
-    fri_pow_witness: String,
-
-    public_inputs: Vec<String>,
+    pub wires_cap: Vec<Vec<String>>,
+    pub plonk_zs_partial_products_cap: Vec<Vec<String>>,
+    pub quotient_polys_cap: Vec<Vec<String>>,
+
+    pub openings_constants: Vec<Vec<String>>,
+    pub openings_plonk_sigmas: Vec<Vec<String>>,
+    pub openings_wires: Vec<Vec<String>>,
+    pub openings_plonk_zs: Vec<Vec<String>>,
+    pub openings_plonk_zs_next: Vec<Vec<String>>,
+    pub openings_partial_products: Vec<Vec<String>>,
+    pub openings_quotient_polys: Vec<Vec<String>>,
+
+    pub fri_commit_phase_merkle_caps: Vec<Vec<Vec<String>>>,
+
+    pub fri_query_init_constants_sigmas_v: Vec<Vec<String>>,
+    pub fri_query_init_constants_sigmas_p: Vec<Vec<Vec<String>>>,
+    pub fri_query_init_wires_v: Vec<Vec<String>>,
+    pub fri_query_init_wires_p: Vec<Vec<Vec<String>>>,
+    pub fri_query_init_zs_partial_v: Vec<Vec<String>>,
+    pub fri_query_init_zs_partial_p: Vec<Vec<Vec<String>>>,
+    pub fri_query_init_quotient_v: Vec<Vec<String>>,
+    pub fri_query_init_quotient_p: Vec<Vec<Vec<String>>>,
+
+    pub fri_query_step0_v: Vec<Vec<Vec<String>>>,
+    pub fri_query_step0_p: Vec<Vec<Vec<String>>>,
+    pub fri_query_step1_v: Vec<Vec<Vec<String>>>,
+    pub fri_query_step1_p: Vec<Vec<Vec<String>>>,
+
+    pub fri_final_poly_ext_v: Vec<Vec<String>>,
+    pub fri_pow_witness: String,
+
+    pub public_inputs: Vec<String>,
 }
 
 // TODO: The input should be CommonCircuitData
@@ -237,10 +185,71 @@ pub fn generate_verifier_config<
         num_fri_final_poly_ext_v: proof.opening_proof.final_poly.coeffs.len(),
 
         num_public_inputs: pwpi.public_inputs.len(),
+        compress_public_inputs: false,
     };
     Ok(conf)
 }
 
+/// Same as `generate_verifier_config`, derived from the circuit instead of one of its proofs,
+/// so the Circom verifier can be generated before any proof exists.
+pub fn verifier_config_from_common<F: RichField + Extendable<D>, const D: usize>(
+    common: &CommonCircuitData<F, D>,
+) -> anyhow::Result<VerifierConfig> {
+    let arity_bits = &common.fri_params.reduction_arity_bits;
+    anyhow::ensure!(
+        arity_bits.len() == 2,
+        "the Circom verifier needs 2 FRI reduction steps, got {}",
+        arity_bits.len()
+    );
+
+    let num_challenges = common.config.num_challenges;
+    let salt_size = if common.fri_params.hiding { SALT_SIZE } else { 0 };
+    let cap_height = common.config.fri_config.cap_height;
+    let num_caps = 1 << cap_height;
+    let lde_bits = common.degree_bits() + common.config.fri_config.rate_bits;
+    let num_quotient_polys = num_challenges * common.quotient_degree_factor;
+
+    Ok(VerifierConfig {
+        hash_size: 32,
+        field_size: 8,
+        ext_field_size: 16,
+        merkle_height_size: 1,
+
+        num_wires_cap: num_caps,
+        num_plonk_zs_partial_products_cap: num_caps,
+        num_quotient_polys_cap: num_caps,
+
+        num_openings_constants: common.num_constants,
+        num_openings_plonk_sigmas: common.config.num_routed_wires,
+        num_openings_wires: common.config.num_wires,
+        num_openings_plonk_zs: num_challenges,
+        num_openings_plonk_zs_next: num_challenges,
+        num_openings_partial_products: num_challenges * common.num_partial_products,
+        num_openings_quotient_polys: num_quotient_polys,
+
+        num_fri_commit_round: arity_bits.len(),
+        fri_commit_merkle_cap_height: num_caps,
+        num_fri_query_round: common.config.fri_config.num_query_rounds,
+        num_fri_query_init_constants_sigmas_v: common.num_constants + common.config.num_routed_wires,
+        num_fri_query_init_constants_sigmas_p: lde_bits - cap_height,
+        num_fri_query_init_wires_v: common.config.num_wires + salt_size,
+        num_fri_query_init_wires_p: lde_bits - cap_height,
+        num_fri_query_init_zs_partial_v: num_challenges * (1 + common.num_partial_products)
+            + salt_size,
+        num_fri_query_init_zs_partial_p: lde_bits - cap_height,
+        num_fri_query_init_quotient_v: num_quotient_polys + salt_size,
+        num_fri_query_init_quotient_p: lde_bits - cap_height,
+        num_fri_query_step0_v: 1 << arity_bits[0],
+        num_fri_query_step0_p: lde_bits - arity_bits[0] - cap_height,
+        num_fri_query_step1_v: 1 << arity_bits[1],
+        num_fri_query_step1_p: lde_bits - arity_bits[0] - arity_bits[1] - cap_height,
+        num_fri_final_poly_ext_v: common.fri_params.final_poly_len(),
+
+        num_public_inputs: common.num_public_inputs,
+        compress_public_inputs: false,
+    })
+}
+
 pub fn generate_proof_base64<
     F: RichField + Extendable<D>,
     C: GenericConfig<D, F = F>,
@@ -249,10 +258,19 @@ pub fn generate_proof_base64<
     pwpi: &ProofWithPublicInputs<F, C, D>,
     conf: &VerifierConfig,
 ) -> anyhow::Result<String> {
-    let mut proof_size: usize =
-        (conf.num_wires_cap + conf.num_plonk_zs_partial_products_cap + conf.num_quotient_polys_cap)
-            * conf.hash_size;
+    let circom_proof = generate_proof_for_circom(pwpi, conf)?;
+    Ok(serde_json::to_string(&circom_proof)?)
+}
 
+pub fn generate_proof_for_circom<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    const D: usize,
+>(
+    pwpi: &ProofWithPublicInputs<F, C, D>,
+    conf: &VerifierConfig,
+) -> anyhow::Result<ProofForCircom> {
+    let start = Instant::now();
     let mut wires_cap = vec![vec!["0".to_string(); 4]; conf.num_wires_cap];
     for i in 0..conf.num_wires_cap {
         let h = pwpi.proof.wires_cap.0[i].to_vec();
@@ -278,15 +296,6 @@ pub fn generate_proof_base64<
         }
     }
 
-    proof_size += (conf.num_openings_constants
-        + conf.num_openings_plonk_sigmas
-        + conf.num_openings_wires
-        + conf.num_openings_plonk_zs
-        + conf.num_openings_plonk_zs_next
-        + conf.num_openings_partial_products
-        + conf.num_openings_quotient_polys)
-        * conf.ext_field_size;
-
     let mut openings_constants = vec![vec!["0".to_string(); 2]; conf.num_openings_constants];
     for i in 0..conf.num_openings_constants {
         openings_constants[i][0] = pwpi.proof.openings.constants[i].to_basefield_array()[0]
@@ -358,8 +367,6 @@ pub fn generate_proof_base64<
         .to_string();
     }
 
-    proof_size += (conf.num_fri_commit_round * conf.fri_commit_merkle_cap_height) * conf.hash_size;
-
     let mut fri_commit_phase_merkle_caps =
         vec![
             vec![vec!["0".to_string(); 4]; conf.fri_commit_merkle_cap_height];
@@ -375,27 +382,6 @@ pub fn generate_proof_base64<
         }
     }
 
-    proof_size += conf.num_fri_query_round
-        * ((conf.num_fri_query_init_constants_sigmas_v
-            + conf.num_fri_query_init_wires_v
-            + conf.num_fri_query_init_zs_partial_v
-            + conf.num_fri_query_init_quotient_v)
-            * conf.field_size
-            + (conf.num_fri_query_init_constants_sigmas_p
-                + conf.num_fri_query_init_wires_p
-                + conf.num_fri_query_init_zs_partial_p
-                + conf.num_fri_query_init_quotient_p)
-                * conf.hash_size
-            + conf.merkle_height_size * 4);
-
-    proof_size += conf.num_fri_query_round
-        * (conf.num_fri_query_step0_v * conf.ext_field_size
-            + conf.num_fri_query_step0_p * conf.hash_size
-            + conf.merkle_height_size
-            + conf.num_fri_query_step1_v * conf.ext_field_size
-            + conf.num_fri_query_step1_p * conf.hash_size
-            + conf.merkle_height_size);
-
     let mut fri_query_init_constants_sigmas_v =
         vec![
             vec!["0".to_string(); conf.num_fri_query_init_constants_sigmas_v];
@@ -577,8 +563,6 @@ pub fn generate_proof_base64<
         }
     }
 
-    proof_size += conf.num_fri_final_poly_ext_v * conf.ext_field_size;
-
     let mut fri_final_poly_ext_v = vec![vec!["0".to_string(); 2]; conf.num_fri_final_poly_ext_v];
     for i in 0..conf.num_fri_final_poly_ext_v {
         fri_final_poly_ext_v[i][0] = pwpi.proof.opening_proof.final_poly.coeffs[i]
@@ -591,10 +575,6 @@ pub fn generate_proof_base64<
             .to_string();
     }
 
-    proof_size += conf.field_size;
-
-    proof_size += conf.num_public_inputs * conf.field_size;
-
     let mut public_inputs = vec!["0".to_string(); conf.num_public_inputs];
     for i in 0..conf.num_public_inputs {
         public_inputs[i] = pwpi.public_inputs[i].to_canonical_u64().to_string();
@@ -634,11 +614,116 @@ pub fn generate_proof_base64<
         public_inputs,
     };
 
-    let proof_bytes = pwpi.to_bytes();
-    assert_eq!(proof_bytes.len(), proof_size);
-    println!("proof size: {}", proof_size);
+    let stats = ProofStats::new(pwpi, conf);
+    anyhow::ensure!(stats.total().bytes == pwpi.to_bytes().len());
+    anyhow::ensure!(
+        stats.total().field_elements == stats.circom_input_signals,
+        "the proof does not match the verifier config"
+    );
+    info!(
+        "Generated the Circom input for a {} byte proof in {:?}",
+        stats.total().bytes,
+        start.elapsed()
+    );
+    debug!("{:?}", stats);
+
+    Ok(circom_proof)
+}
+
+/// One selector group of the gate evaluation, for every gate but `NoopGate`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SelectorGroup {
+    pub gate: String,
+    pub gate_index: usize,
+    pub selector_index: usize,
+    pub group_start: usize,
+    pub group_end: usize,
+}
 
-    Ok(serde_json::to_string(&circom_proof).unwrap())
+/// The values `generate_circom_verifier` derives from the circuit and writes into
+/// `constants.circom`, as canonical u64s.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CircomConstants {
+    pub k_is: Vec<u64>,
+    pub reduction_arity_bits: Vec<usize>,
+    pub circuit_digest: Vec<u64>,
+    pub sigma_cap: Vec<Vec<u64>>,
+    pub degree_bits: usize,
+    pub rate_bits: usize,
+    pub num_challenges: usize,
+    pub num_gate_constraints: usize,
+    pub quotient_degree_factor: usize,
+    pub min_fri_pow_response: u32,
+    pub g_from_degree_bits: [u64; 2],
+    pub log_size_of_lde_domain: usize,
+    pub multiplicative_group_generator: u64,
+    pub primitive_root_of_unity_lde: u64,
+    pub zero_knowledge: bool,
+    /// Generators of the subgroups of order `2^1` to `2^4`, for the FRI steps.
+    pub g_arity_bits: [u64; 4],
+    pub num_selectors: usize,
+    pub selector_groups: Vec<SelectorGroup>,
+}
+
+pub fn circom_constants<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    const D: usize,
+>(
+    common: &CommonCircuitData<F, D>,
+    verifier_only: &VerifierOnlyCircuitData<C, D>,
+) -> CircomConstants {
+    let log_n = log2_strict(common.fri_params.lde_size());
+    let g = F::Extension::primitive_root_of_unity(common.degree_bits()).to_basefield_array();
+    let selector_groups = common
+        .gates
+        .iter()
+        .enumerate()
+        .filter(|(_, gate)| gate.0.id() != "NoopGate")
+        .map(|(gate_index, gate)| {
+            let selector_index = common.selectors_info.selector_indices[gate_index];
+            let group = &common.selectors_info.groups[selector_index];
+            SelectorGroup {
+                gate: gate.0.id(),
+                gate_index,
+                selector_index,
+                group_start: group.start,
+                group_end: group.end,
+            }
+        })
+        .collect();
+
+    CircomConstants {
+        k_is: common.k_is.iter().map(|k| k.to_canonical_u64()).collect(),
+        reduction_arity_bits: common.fri_params.reduction_arity_bits.clone(),
+        circuit_digest: verifier_only
+            .circuit_digest
+            .to_vec()
+            .iter()
+            .map(|x| x.to_canonical_u64())
+            .collect(),
+        sigma_cap: verifier_only
+            .constants_sigmas_cap
+            .0
+            .iter()
+            .map(|h| h.to_vec().iter().map(|x| x.to_canonical_u64()).collect())
+            .collect(),
+        degree_bits: common.degree_bits(),
+        rate_bits: common.config.fri_config.rate_bits,
+        num_challenges: common.config.num_challenges,
+        num_gate_constraints: common.num_gate_constraints,
+        quotient_degree_factor: common.quotient_degree_factor,
+        min_fri_pow_response: common.config.fri_config.proof_of_work_bits
+            + (64 - F::order().bits()) as u32,
+        g_from_degree_bits: [g[0].to_canonical_u64(), g[1].to_canonical_u64()],
+        log_size_of_lde_domain: log_n,
+        multiplicative_group_generator: F::MULTIPLICATIVE_GROUP_GENERATOR.to_canonical_u64(),
+        primitive_root_of_unity_lde: F::primitive_root_of_unity(log_n).to_canonical_u64(),
+        zero_knowledge: common.config.zero_knowledge,
+        g_arity_bits: [1, 2, 3, 4].map(|bits| F::primitive_root_of_unity(bits).to_canonical_u64()),
+        num_selectors: common.selectors_info.num_selectors(),
+        selector_groups,
+    }
 }
 
 pub fn generate_circom_verifier<
@@ -649,27 +734,31 @@ pub fn generate_circom_verifier<
     conf: &VerifierConfig,
     common: &CommonCircuitData<F, D>,
     verifier_only: &VerifierOnlyCircuitData<C, D>,
-) -> anyhow::Result<(String, String)> {
+    min_security_bits: Option<usize>,
+) -> anyhow::Result<(String, String, CircomConstants)> {
     assert_eq!(F::BITS, 64);
     assert_eq!(F::Extension::BITS, 128);
-    println!("Generating Circom files ...");
+    if let Some(min_bits) = min_security_bits {
+        SecurityLevel::from_common(common).ensure_at_least(min_bits)?;
+    }
+    let start = Instant::now();
+    let values = circom_constants(common, verifier_only);
 
     // Load template contract
-    let mut constants = std::fs::read_to_string("./src/template_constants.circom")
-        .expect("Something went wrong reading the file");
+    let mut constants = include_str!("template_constants.circom").to_owned();
 
-    let k_is = &common.k_is;
+    let k_is = &values.k_is;
     let mut k_is_str = "".to_owned();
     for i in 0..k_is.len() {
         k_is_str += &*("  k_is[".to_owned()
             + &*i.to_string()
             + "] = "
-            + &*k_is[i].to_canonical_u64().to_string()
+            + &*k_is[i].to_string()
             + ";\n");
     }
     constants = constants.replace("  $SET_K_IS;\n", &*k_is_str);
 
-    let reduction_arity_bits = &common.fri_params.reduction_arity_bits;
+    let reduction_arity_bits = &values.reduction_arity_bits;
     let mut reduction_arity_bits_str = "".to_owned();
     for i in 0..reduction_arity_bits.len() {
         reduction_arity_bits_str += &*("  bits[".to_owned()
@@ -685,6 +774,10 @@ pub fn generate_circom_verifier<
     );
 
     constants = constants.replace("$NUM_PUBLIC_INPUTS", &*conf.num_public_inputs.to_string());
+    constants = constants.replace(
+        "$PUBLIC_INPUTS_PER_SIGNAL",
+        &*conf.public_inputs_per_signal().to_string(),
+    );
     constants = constants.replace("$NUM_WIRES_CAP", &*conf.num_wires_cap.to_string());
     constants = constants.replace(
         "$NUM_PLONK_ZS_PARTIAL_PRODUCTS_CAP",
@@ -785,85 +878,72 @@ pub fn generate_circom_verifier<
     );
     constants = constants.replace(
         "$NUM_CHALLENGES",
-        &*common.config.num_challenges.to_string(),
+        &*values.num_challenges.to_string(),
     );
 
-    let circuit_digest = verifier_only.circuit_digest.to_vec();
+    let circuit_digest = &values.circuit_digest;
     let mut circuit_digest_str = "".to_owned();
     for i in 0..circuit_digest.len() {
         circuit_digest_str += &*("  cd[".to_owned()
             + &*i.to_string()
             + "] = "
-            + &*circuit_digest[i].to_canonical_u64().to_string()
+            + &*circuit_digest[i].to_string()
             + ";\n");
     }
     constants = constants.replace("  $SET_CIRCUIT_DIGEST;\n", &*circuit_digest_str);
 
-    constants = constants.replace(
-        "$FRI_RATE_BITS",
-        &*common.config.fri_config.rate_bits.to_string(),
-    );
-    constants = constants.replace("$DEGREE_BITS", &*common.degree_bits().to_string());
+    constants = constants.replace("$FRI_RATE_BITS", &*values.rate_bits.to_string());
+    constants = constants.replace("$DEGREE_BITS", &*values.degree_bits.to_string());
     constants = constants.replace(
         "$NUM_GATE_CONSTRAINTS",
-        &*common.num_gate_constraints.to_string(),
+        &*values.num_gate_constraints.to_string(),
     );
     constants = constants.replace(
         "$QUOTIENT_DEGREE_FACTOR",
-        &*common.quotient_degree_factor.to_string(),
+        &*values.quotient_degree_factor.to_string(),
     );
     constants = constants.replace(
         "$MIN_FRI_POW_RESPONSE",
-        &*(common.config.fri_config.proof_of_work_bits + (64 - F::order().bits()) as u32)
-            .to_string(),
+        &*values.min_fri_pow_response.to_string(),
     );
-    let g = F::Extension::primitive_root_of_unity(common.degree_bits());
     constants = constants.replace(
         "$G_FROM_DEGREE_BITS_0",
-        &g.to_basefield_array()[0].to_string(),
+        &values.g_from_degree_bits[0].to_string(),
     );
     constants = constants.replace(
         "$G_FROM_DEGREE_BITS_1",
-        &g.to_basefield_array()[1].to_string(),
+        &values.g_from_degree_bits[1].to_string(),
+    );
+    constants = constants.replace(
+        "$LOG_SIZE_OF_LDE_DOMAIN",
+        &*values.log_size_of_lde_domain.to_string(),
     );
-    let log_n = log2_strict(common.fri_params.lde_size());
-    constants = constants.replace("$LOG_SIZE_OF_LDE_DOMAIN", &*log_n.to_string());
     constants = constants.replace(
         "$MULTIPLICATIVE_GROUP_GENERATOR",
-        &*F::MULTIPLICATIVE_GROUP_GENERATOR.to_string(),
+        &*values.multiplicative_group_generator.to_string(),
     );
     constants = constants.replace(
         "$PRIMITIVE_ROOT_OF_UNITY_LDE",
-        &*F::primitive_root_of_unity(log_n).to_string(),
+        &*values.primitive_root_of_unity_lde.to_string(),
     );
     // TODO: add test with config zero_knoledge = true
-    constants = constants.replace(
-        "$ZERO_KNOWLEDGE",
-        &*common.config.zero_knowledge.to_string(),
-    );
-    let g = F::primitive_root_of_unity(1);
-    constants = constants.replace("$G_ARITY_BITS_1", &g.to_string());
-    let g = F::primitive_root_of_unity(2);
-    constants = constants.replace("$G_ARITY_BITS_2", &g.to_string());
-    let g = F::primitive_root_of_unity(3);
-    constants = constants.replace("$G_ARITY_BITS_3", &g.to_string());
-    let g = F::primitive_root_of_unity(4);
-    constants = constants.replace("$G_ARITY_BITS_4", &g.to_string());
+    constants = constants.replace("$ZERO_KNOWLEDGE", &*values.zero_knowledge.to_string());
+    for (i, g) in values.g_arity_bits.iter().enumerate() {
+        constants = constants.replace(&format!("$G_ARITY_BITS_{}", i + 1), &g.to_string());
+    }
 
     // Load gate template
-    let mut gates_lib = std::fs::read_to_string("./src/template_gates.circom")
-        .expect("Something went wrong reading the file");
+    let mut gates_lib = include_str!("template_gates.circom").to_owned();
 
-    let num_selectors = common.selectors_info.num_selectors();
+    let num_selectors = values.num_selectors;
     constants = constants.replace("$NUM_SELECTORS", &num_selectors.to_string());
     let mut evaluate_gate_constraints_str = "".to_owned();
     let mut last_component_name = "".to_owned();
-    for (row, gate) in common.gates.iter().enumerate() {
-        if gate.0.id().eq("NoopGate") {
-            continue;
-        }
-        let selector_index = common.selectors_info.selector_indices[row];
-        let group_range = common.selectors_info.groups[selector_index].clone();
+    for selector_group in &values.selector_groups {
+        let row = selector_group.gate_index;
+        let gate = &common.gates[row];
+        let selector_index = selector_group.selector_index;
+        let group_range = selector_group.group_start..selector_group.group_end;
         let mut c = 0;
 
         evaluate_gate_constraints_str = evaluate_gate_constraints_str + "\n";
@@ -951,38 +1031,99 @@ pub fn generate_circom_verifier<
     gates_lib = gates_lib.replace("$NUM_OPENINGS_WIRES", &*conf.num_openings_wires.to_string());
     gates_lib = gates_lib.replace("$F_EXT_W", &*F::W.to_basefield_array()[0].to_string());
 
-    let sigma_cap_count = 1 << common.config.fri_config.cap_height;
+    let sigma_cap_count = values.sigma_cap.len();
     constants = constants.replace("$SIGMA_CAP_COUNT", &*sigma_cap_count.to_string());
 
     let mut sigma_cap_str = "".to_owned();
     for i in 0..sigma_cap_count {
-        let cap = verifier_only.constants_sigmas_cap.0[i];
-        let hash = cap.to_vec();
+        let hash = &values.sigma_cap[i];
         assert_eq!(hash.len(), 4);
         sigma_cap_str += &*("  sc[".to_owned()
             + &*i.to_string()
             + "][0] = "
-            + &*hash[0].to_canonical_u64().to_string()
+            + &*hash[0].to_string()
             + ";\n");
         sigma_cap_str += &*("  sc[".to_owned()
             + &*i.to_string()
             + "][1] = "
-            + &*hash[1].to_canonical_u64().to_string()
+            + &*hash[1].to_string()
             + ";\n");
         sigma_cap_str += &*("  sc[".to_owned()
             + &*i.to_string()
             + "][2] = "
-            + &*hash[2].to_canonical_u64().to_string()
+            + &*hash[2].to_string()
             + ";\n");
         sigma_cap_str += &*("  sc[".to_owned()
             + &*i.to_string()
             + "][3] = "
-            + &*hash[3].to_canonical_u64().to_string()
+            + &*hash[3].to_string()
             + ";\n");
     }
     constants = constants.replace("  $SET_SIGMA_CAP;\n", &*sigma_cap_str);
 
-    Ok((constants, gates_lib))
+    info!("Generated the Circom verifier in {:?}", start.elapsed());
+    Ok((constants, gates_lib, values))
+}
+
+/// Generates the `Plonky2Verifier` Solidity contract for `conf` and its ABI. The contract takes
+/// the plonky2 public inputs as `uint64`s, packs them into public signals if
+/// `compress_public_inputs` is set, and forwards the Groth16 proof to the verifier exported by
+/// snarkjs.
+pub fn generate_solidity_wrapper(conf: &VerifierConfig) -> anyhow::Result<(String, String)> {
+    // Solidity has no zero-length static arrays, and snarkjs exports no verifier without them.
+    anyhow::ensure!(
+        conf.num_public_inputs > 0,
+        "the Solidity wrapper needs at least one public input"
+    );
+    let contract = include_str!("template_wrapper.sol")
+        .replace("$NUM_PUBLIC_INPUTS", &conf.num_public_inputs.to_string())
+        .replace("$NUM_PUBLIC_SIGNALS", &conf.num_public_signals().to_string())
+        .replace(
+            "$PUBLIC_INPUTS_PER_SIGNAL",
+            &conf.public_inputs_per_signal().to_string(),
+        );
+
+    let public_inputs = format!("uint64[{}]", conf.num_public_inputs);
+    let signals = format!("uint256[{}]", conf.num_public_signals());
+    let abi = serde_json::json!([
+        {
+            "type": "constructor",
+            "stateMutability": "nonpayable",
+            "inputs": [{ "name": "groth16Verifier", "type": "address", "internalType": "address" }],
+        },
+        {
+            "type": "function",
+            "name": "verifier",
+            "stateMutability": "view",
+            "inputs": [],
+            "outputs": [{
+                "name": "",
+                "type": "address",
+                "internalType": "contract IGroth16Verifier",
+            }],
+        },
+        {
+            "type": "function",
+            "name": "publicSignals",
+            "stateMutability": "pure",
+            "inputs": [{ "name": "publicInputs", "type": public_inputs, "internalType": public_inputs }],
+            "outputs": [{ "name": "signals", "type": signals, "internalType": signals }],
+        },
+        {
+            "type": "function",
+            "name": "verifyProof",
+            "stateMutability": "view",
+            "inputs": [
+                { "name": "publicInputs", "type": public_inputs, "internalType": public_inputs },
+                { "name": "a", "type": "uint256[2]", "internalType": "uint256[2]" },
+                { "name": "b", "type": "uint256[2][2]", "internalType": "uint256[2][2]" },
+                { "name": "c", "type": "uint256[2]", "internalType": "uint256[2]" },
+            ],
+            "outputs": [{ "name": "", "type": "bool", "internalType": "bool" }],
+        },
+    ]);
+
+    Ok((contract, serde_json::to_string_pretty(&abi)?))
 }
 
 #[cfg(test)]
@@ -993,12 +1134,17 @@ mod tests {
 
     use crate::config::PoseidonBN128GoldilocksConfig;
     use anyhow::Result;
-    use plonky2::field::extension::Extendable;
+    use plonky2::field::cosets::get_unique_coset_shifts;
+    use plonky2::field::extension::{Extendable, FieldExtension};
+    use plonky2::field::types::{Field, Field64, PrimeField64};
     use plonky2::fri::reduction_strategies::FriReductionStrategy;
     use plonky2::fri::FriConfig;
-    use plonky2::hash::hash_types::RichField;
+    use plonky2::hash::hash_types::{HashOut, RichField};
+    use plonky2::hash::merkle_tree::MerkleCap;
     use plonky2::iop::witness::WitnessWrite;
-    use plonky2::plonk::circuit_data::{CommonCircuitData, VerifierOnlyCircuitData};
+    use plonky2::plonk::circuit_data::{
+        CommonCircuitData, VerifierCircuitData, VerifierOnlyCircuitData,
+    };
     use plonky2::plonk::config::{Hasher, PoseidonGoldilocksConfig};
     use plonky2::plonk::proof::ProofWithPublicInputs;
     use plonky2::{
@@ -1009,9 +1155,16 @@ mod tests {
         },
     };
 
+    use crate::recursive::recursive_proof;
     use crate::verifier::{
-        generate_circom_verifier, generate_proof_base64, generate_verifier_config, recursive_proof,
+        generate_circom_verifier, generate_proof_base64, generate_solidity_wrapper,
+        generate_verifier_config, verifier_config_from_common, VerifierConfig,
+    };
+    use crate::serialization::{
+        read_common_data, read_proof, read_verifier_data, write_common_data, write_proof,
+        write_verifier_data,
     };
+    use crate::test_utils::{final_config, high_rate_config, unique_temp_dir};
 
     /// Creates a dummy proof which should have roughly `num_dummy_gates` gates.
     fn dummy_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
@@ -1081,7 +1234,7 @@ mod tests {
         let (proof, vd, cd) = dummy_proof::<F, C, D>(&final_config, 4_000, 0)?;
 
         let conf = generate_verifier_config(&proof)?;
-        let (circom_constants, circom_gates) = generate_circom_verifier(&conf, &cd, &vd)?;
+        let (circom_constants, circom_gates, _) = generate_circom_verifier(&conf, &cd, &vd, None)?;
 
         let mut circom_file = File::create("./circom/circuits/constants.circom")?;
         circom_file.write_all(circom_constants.as_bytes())?;
@@ -1103,6 +1256,259 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_circom_constants() -> Result<()> {
+        const D: usize = 2;
+        type C = PoseidonBN128GoldilocksConfig;
+        type F = <C as GenericConfig<D>>::F;
+        type FE = <F as Extendable<D>>::Extension;
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let final_config = CircuitConfig {
+            num_routed_wires: 37,
+            fri_config: FriConfig {
+                rate_bits: 8,
+                cap_height: 0,
+                proof_of_work_bits: 20,
+                reduction_strategy: FriReductionStrategy::MinSize(None),
+                num_query_rounds: 10,
+            },
+            ..standard_config
+        };
+        let (proof, vd, cd) = dummy_proof::<F, C, D>(&final_config, 4_000, 4)?;
+
+        let conf = verifier_config_from_common(&cd)?;
+        let (circom_constants, _, constants) = generate_circom_verifier(&conf, &cd, &vd, None)?;
+        assert!(!circom_constants.contains('$'));
+        // The final config has 100 bits of FRI soundness, capped further by the challenges.
+        assert!(generate_circom_verifier(&conf, &cd, &vd, Some(101)).is_err());
+
+        // plonky2 accepts the proof against the digest and sigma cap taken from the constants.
+        let to_hash = |h: &[u64]| HashOut {
+            elements: [0, 1, 2, 3].map(|i| F::from_canonical_u64(h[i])),
+        };
+        let verifier_data = VerifierCircuitData::<F, C, D> {
+            verifier_only: VerifierOnlyCircuitData {
+                constants_sigmas_cap: MerkleCap(
+                    constants.sigma_cap.iter().map(|h| to_hash(h)).collect(),
+                ),
+                circuit_digest: to_hash(&constants.circuit_digest),
+            },
+            common: cd.clone(),
+        };
+        verifier_data.verify(proof.clone())?;
+
+        let public_inputs_hash =
+            <C as GenericConfig<D>>::InnerHasher::hash_no_pad(&proof.public_inputs);
+        let challenges = proof.get_challenges(
+            public_inputs_hash,
+            &verifier_data.verifier_only.circuit_digest,
+            &cd,
+        )?;
+        let fri_challenges = &challenges.fri_challenges;
+        assert!(
+            fri_challenges.fri_pow_response.to_canonical_u64().leading_zeros()
+                >= constants.min_fri_pow_response
+        );
+        assert_eq!(
+            constants.min_fri_pow_response,
+            (cd.config.fri_config.proof_of_work_bits as usize + 64 - F::BITS) as u32
+        );
+        assert_eq!(
+            constants.log_size_of_lde_domain,
+            constants.degree_bits + constants.rate_bits
+        );
+        assert!(fri_challenges
+            .fri_query_indices
+            .iter()
+            .all(|&i| i < 1 << constants.log_size_of_lde_domain));
+        assert_eq!(constants.reduction_arity_bits.len(), fri_challenges.fri_betas.len());
+        assert_eq!(constants.num_challenges, challenges.plonk_betas.len());
+
+        // plonky2 opens `plonk_zs_next` at `zeta * g`, the second point of its FRI instance.
+        let g = FE::from_basefield_array(constants.g_from_degree_bits.map(F::from_canonical_u64));
+        let fri_instance = cd.get_fri_instance(challenges.plonk_zeta);
+        assert_eq!(fri_instance.batches[1].point, challenges.plonk_zeta * g);
+
+        let root = F::from_canonical_u64(constants.primitive_root_of_unity_lde);
+        assert_eq!(root.exp_power_of_2(constants.log_size_of_lde_domain), F::ONE);
+        assert_ne!(root.exp_power_of_2(constants.log_size_of_lde_domain - 1), F::ONE);
+        for (i, g) in constants.g_arity_bits.iter().enumerate() {
+            let g = F::from_canonical_u64(*g);
+            assert_eq!(g.exp_power_of_2(i + 1), F::ONE);
+            assert_ne!(g.exp_power_of_2(i), F::ONE);
+        }
+        // The LDE coset is shifted by a generator of the whole multiplicative group.
+        let shift = F::from_canonical_u64(constants.multiplicative_group_generator);
+        for q in [2, 3, 5, 17, 257, 65537] {
+            assert_ne!(shift.exp_u64((F::ORDER - 1) / q), F::ONE);
+        }
+
+        let k_is: Vec<u64> = get_unique_coset_shifts::<F>(cd.degree(), cd.config.num_routed_wires)
+            .iter()
+            .map(|k| k.to_canonical_u64())
+            .collect();
+        assert_eq!(constants.k_is, k_is);
+
+        // Every gate but `NoopGate` is evaluated under the selector group plonky2 assigned it.
+        assert_eq!(constants.num_selectors, cd.selectors_info.num_selectors());
+        let gates: Vec<(usize, String)> = cd
+            .gates
+            .iter()
+            .enumerate()
+            .filter(|(_, gate)| gate.0.id() != "NoopGate")
+            .map(|(i, gate)| (i, gate.0.id()))
+            .collect();
+        assert!(!gates.is_empty());
+        assert_eq!(
+            constants
+                .selector_groups
+                .iter()
+                .map(|group| (group.gate_index, group.gate.clone()))
+                .collect::<Vec<_>>(),
+            gates
+        );
+        for group in &constants.selector_groups {
+            let selector_index = cd.selectors_info.selector_indices[group.gate_index];
+            let range = &cd.selectors_info.groups[selector_index];
+            assert_eq!(group.selector_index, selector_index);
+            assert_eq!(group.group_start..group.group_end, *range);
+            assert!(range.contains(&group.gate_index));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_verifier_config_from_common() -> Result<()> {
+        const D: usize = 2;
+        type C = PoseidonBN128GoldilocksConfig;
+        type F = <C as GenericConfig<D>>::F;
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let final_config = CircuitConfig {
+            num_routed_wires: 37,
+            fri_config: FriConfig {
+                rate_bits: 8,
+                cap_height: 0,
+                proof_of_work_bits: 20,
+                reduction_strategy: FriReductionStrategy::MinSize(None),
+                num_query_rounds: 10,
+            },
+            ..standard_config
+        };
+        let (proof, _, cd) = dummy_proof::<F, C, D>(&final_config, 4_000, 4)?;
+
+        assert_eq!(verifier_config_from_common(&cd)?, generate_verifier_config(&proof)?);
+        Ok(())
+    }
+
+    /// The ABI types of the parameters and return values of the `Plonky2Verifier` declaration
+    /// starting with `header`, and its state mutability.
+    fn solidity_signature(contract: &str, header: &str) -> (Vec<String>, Vec<String>, String) {
+        let body = &contract[contract.find("contract Plonky2Verifier").unwrap()..];
+        let start = body.find(header).unwrap() + header.len();
+        let decl = &body[start..start + body[start..].find('{').unwrap()];
+        let types = |list: &str| -> Vec<String> {
+            list.split(',')
+                .filter_map(|param| param.split_whitespace().next())
+                .map(|ty| match ty.strip_prefix("uint") {
+                    Some(dims) if dims.is_empty() || dims.starts_with('[') => {
+                        format!("uint256{}", dims)
+                    }
+                    _ => ty.to_owned(),
+                })
+                .collect()
+        };
+        let inputs = types(&decl[..decl.find(')').unwrap()]);
+        let outputs = match decl.find("returns (") {
+            Some(i) => types(&decl[i + 9..i + decl[i..].find(')').unwrap()]),
+            None => vec![],
+        };
+        let mutability = ["pure", "view"]
+            .into_iter()
+            .find(|m| decl.split_whitespace().any(|w| w == *m))
+            .unwrap_or("nonpayable");
+        (inputs, outputs, mutability.to_owned())
+    }
+
+    #[test]
+    fn test_solidity_wrapper() -> Result<()> {
+        const D: usize = 2;
+        type C = PoseidonBN128GoldilocksConfig;
+        type F = <C as GenericConfig<D>>::F;
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let final_config = CircuitConfig {
+            num_routed_wires: 37,
+            fri_config: FriConfig {
+                rate_bits: 8,
+                cap_height: 0,
+                proof_of_work_bits: 20,
+                reduction_strategy: FriReductionStrategy::MinSize(None),
+                num_query_rounds: 10,
+            },
+            ..standard_config
+        };
+        let (_, vd, cd) = dummy_proof::<F, C, D>(&final_config, 4_000, 4)?;
+        let conf = verifier_config_from_common(&cd)?;
+
+        for compress_public_inputs in [false, true] {
+            let conf = VerifierConfig {
+                compress_public_inputs,
+                ..conf.clone()
+            };
+            let (contract, abi) = generate_solidity_wrapper(&conf)?;
+            assert!(!contract.contains('$'));
+
+            // The ABI matches the declarations of the contract.
+            let abi: serde_json::Value = serde_json::from_str(&abi)?;
+            let types = |params: &serde_json::Value| -> Vec<String> {
+                params
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|p| p["type"].as_str().unwrap().to_owned())
+                    .collect()
+            };
+            for entry in abi.as_array().unwrap() {
+                if entry["name"] == "verifier" {
+                    // The getter of a public state variable.
+                    assert!(contract.contains("IGroth16Verifier public immutable verifier;"));
+                    continue;
+                }
+                let header = match entry["type"].as_str().unwrap() {
+                    "constructor" => "constructor(".to_owned(),
+                    _ => format!("function {}(", entry["name"].as_str().unwrap()),
+                };
+                let (inputs, outputs, mutability) = solidity_signature(&contract, &header);
+                assert_eq!(types(&entry["inputs"]), inputs);
+                if entry["type"] == "function" {
+                    assert_eq!(types(&entry["outputs"]), outputs);
+                }
+                assert_eq!(entry["stateMutability"], mutability);
+            }
+            let body = &contract[contract.find("contract Plonky2Verifier").unwrap()..];
+            assert_eq!(abi.as_array().unwrap().len(), body.matches("function ").count() + 2);
+
+            // Four public inputs take two public signals when packed.
+            let (inputs, outputs, _) = solidity_signature(&contract, "function publicSignals(");
+            assert_eq!(inputs, vec!["uint64[4]"]);
+            let num_signals = if compress_public_inputs { 2 } else { 4 };
+            assert_eq!(outputs, vec![format!("uint256[{}]", num_signals)]);
+            assert!(contract.contains(&format!("uint[{}] calldata input", num_signals)));
+
+            let (constants, _, _) = generate_circom_verifier(&conf, &cd, &vd, None)?;
+            assert!(constants.contains(&format!(
+                "function PUBLIC_INPUTS_PER_SIGNAL() {{ return {}; }}",
+                if compress_public_inputs { 3 } else { 1 }
+            )));
+        }
+
+        let conf = VerifierConfig {
+            num_public_inputs: 0,
+            ..conf
+        };
+        assert!(generate_solidity_wrapper(&conf).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_verifier_with_public_inputs() -> Result<()> {
         const D: usize = 2;
@@ -1112,7 +1518,7 @@ mod tests {
         let (proof, vd, cd) = dummy_proof::<F, C, D>(&standard_config, 4_000, 4)?;
 
         let conf = generate_verifier_config(&proof)?;
-        let (circom_constants, circom_gates) = generate_circom_verifier(&conf, &cd, &vd)?;
+        let (circom_constants, circom_gates, _) = generate_circom_verifier(&conf, &cd, &vd, None)?;
 
         let mut circom_file = File::create("./circom/circuits/constants.circom")?;
         circom_file.write_all(circom_constants.as_bytes())?;
@@ -1165,14 +1571,14 @@ mod tests {
 
         let (proof, vd, cd) = dummy_proof::<F, C, D>(&standard_config, 4_000, 4)?;
         let (proof, vd, cd) =
-            recursive_proof::<F, C, C, D>(proof, vd, cd, &high_rate_config, None, true, true)?;
+            recursive_proof::<F, C, C, D>(proof, vd, cd, None, &high_rate_config, None)?;
 
         type CBn128 = PoseidonBN128GoldilocksConfig;
         let (proof, vd, cd) =
-            recursive_proof::<F, CBn128, C, D>(proof, vd, cd, &final_config, None, true, true)?;
+            recursive_proof::<F, CBn128, C, D>(proof, vd, cd, None, &final_config, None)?;
 
         let conf = generate_verifier_config(&proof)?;
-        let (circom_constants, circom_gates) = generate_circom_verifier(&conf, &cd, &vd)?;
+        let (circom_constants, circom_gates, _) = generate_circom_verifier(&conf, &cd, &vd, None)?;
 
         let mut circom_file = File::create("./circom/circuits/constants.circom")?;
         circom_file.write_all(circom_constants.as_bytes())?;
@@ -1193,4 +1599,33 @@ mod tests {
 
         Ok(())
     }
+
+    /// The inputs of the `plonky2-circom` tool, for the same final proof as
+    /// `test_recursive_verifier`, read back into a verifiable proof.
+    #[test]
+    fn test_export_tool_inputs() -> Result<()> {
+        const D: usize = 2;
+        type C = PoseidonGoldilocksConfig;
+        type F = <C as GenericConfig<D>>::F;
+        type CBn128 = PoseidonBN128GoldilocksConfig;
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let (proof, vd, cd) = dummy_proof::<F, C, D>(&standard_config, 4_000, 4)?;
+        let (proof, vd, cd) =
+            recursive_proof::<F, C, C, D>(proof, vd, cd, None, &high_rate_config(), None)?;
+        let (proof, vd, cd) =
+            recursive_proof::<F, CBn128, C, D>(proof, vd, cd, None, &final_config(), None)?;
+
+        let dir = unique_temp_dir();
+        write_common_data(dir.join("common.bin"), &cd)?;
+        write_verifier_data(dir.join("verifier_data.bin"), &vd)?;
+        write_proof(dir.join("proof.bin"), &proof)?;
+
+        let common = read_common_data::<F, D, _>(dir.join("common.bin"))?;
+        let verifier_data = VerifierCircuitData::<F, CBn128, D> {
+            verifier_only: read_verifier_data(dir.join("verifier_data.bin"))?,
+            common: common.clone(),
+        };
+        verifier_data.verify(read_proof(dir.join("proof.bin"), &common)?)?;
+        Ok(())
+    }
 }
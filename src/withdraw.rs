@@ -1,3 +1,5 @@
+use anyhow::anyhow;
+use anyhow::ensure;
 use anyhow::Ok;
 use anyhow::Result;
 use log::Level;
@@ -26,6 +28,7 @@ use plonky2::plonk::config::Hasher;
 use plonky2::plonk::proof::ProofWithPublicInputs;
 use plonky2::plonk::prover::prove;
 use plonky2::util::timing::TimingTree;
+use crate::types::BALANCE_BITS;
 use crate::types::POSITION_TREE_DEPTH;
 use crate::types::ProofTuple;
 use crate::types::Transaction;
@@ -40,7 +43,9 @@ pub struct WithdrawTargets {
     pub root_before_target: HashOutTarget,
     pub root_after_target: HashOutTarget,
     pub merkle_proof_target: MerkleProofTarget,
+    pub private_key_target: Vec<Target>,
     pub balance_before_target: Target,
+    pub nonce_target: Target,
     pub position_index_target: Target,
     pub amount_target: Target,
 }
@@ -54,23 +59,38 @@ pub fn withdraw_circuit<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>
         siblings: builder.add_virtual_hashes(tree_height),
     };
 
+    // Only the owner can withdraw: the public key in the leaf is the Poseidon hash of the
+    // private key, which is part of the witness.
+    let private_key_target = builder.add_virtual_targets(4);
+    let pubkey_target = builder.hash_n_to_hash_no_pad::<PoseidonHash>(private_key_target.clone());
     let balance_before_target = builder.add_virtual_target();
+    let nonce_target = builder.add_virtual_target();
 
     let position_index_target = builder.add_virtual_target();
     let position_index_bits = builder.split_le(position_index_target, tree_height);
     
     builder.verify_merkle_proof_to_cap::<PoseidonHash>(
-        vec![balance_before_target],
+        [pubkey_target.elements.to_vec(), vec![balance_before_target, nonce_target]].concat(),
         &position_index_bits,
         &MerkleCapTarget(vec![root_before_target]),
         &merkle_proof_target,
     );
 
+    builder.range_check(balance_before_target, BALANCE_BITS);
+
     let amount_target = builder.add_virtual_target();
+    builder.range_check(amount_target, BALANCE_BITS);
+    // A withdrawal larger than the balance wraps around to at least p - 2^BALANCE_BITS, above
+    // 2^BALANCE_BITS, so the range check on the new balance enforces amount <= balance.
     let balance_after_target = builder.sub(balance_before_target, amount_target);
+    builder.range_check(balance_after_target, BALANCE_BITS);
+
+    // The nonce is bumped so the same signature cannot be replayed.
+    let one = builder.one();
+    let nonce_after_target = builder.add(nonce_target, one);
 
     builder.verify_merkle_proof_to_cap::<PoseidonHash>(
-        vec![balance_after_target],
+        [pubkey_target.elements.to_vec(), vec![balance_after_target, nonce_after_target]].concat(),
         &position_index_bits,
         &MerkleCapTarget(vec![root_after_target]),
         &merkle_proof_target,
@@ -79,16 +99,30 @@ pub fn withdraw_circuit<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>
     builder.register_public_inputs(&root_before_target.elements);
     builder.register_public_inputs(&root_after_target.elements);
 
+    // The owner's signature over (index, amount, nonce), see `types::withdraw_signature`.
+    let signature_target = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+        [
+            private_key_target.clone(),
+            vec![position_index_target, amount_target, nonce_target],
+        ]
+        .concat(),
+    );
+    builder.register_public_inputs(&signature_target.elements);
+
     WithdrawTargets {
         root_before_target,
         root_after_target,
         merkle_proof_target,
+        private_key_target,
         balance_before_target,
+        nonce_target,
         position_index_target,
         amount_target,
     }
 }
 
+/// Proves a withdrawal. Only the owner of the position can run this, as the witness includes
+/// their private key; the sequencer checks the result with `check_withdraw_proof`.
 pub fn make_withdraw_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
     config: &CircuitConfig,
     withdraw_tx: Transaction<F,D>
@@ -98,12 +132,14 @@ pub fn make_withdraw_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F =
 {
 
     let mut builder = CircuitBuilder::<F, D>::new(config.clone());
-    let withdraw_targets = withdraw_circuit::<F, C, D>(POSITION_TREE_DEPTH, &mut builder);
+    let withdraw_targets = withdraw_circuit::<F, C, D>(withdraw_tx.position_tree_depth, &mut builder);
     let WithdrawTargets {
         root_before_target,
         root_after_target,
         merkle_proof_target,
+        private_key_target,
         balance_before_target,
+        nonce_target,
         position_index_target,
         amount_target,
     } = withdraw_targets;
@@ -113,17 +149,26 @@ pub fn make_withdraw_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F =
         root_before,
         root_after,
         merkle_proof,
+        pubkey,
         balance_before,
+        nonce,
         position_index,
         amount,
         position_tree_depth,
+        private_key,
+        transfer_to,
     } = withdraw_tx;
+    let private_key = private_key.ok_or_else(|| anyhow!("withdrawal without the owner's private key"))?;
 
     let mut pw: PartialWitness<F> = PartialWitness::new();
     pw.set_hash_target(root_before_target, root_before);
     pw.set_hash_target(root_after_target, root_after);
 
+    for (t, k) in private_key_target.into_iter().zip(private_key) {
+        pw.set_target(t, k);
+    }
     pw.set_target(balance_before_target, balance_before);
+    pw.set_target(nonce_target, nonce);
     pw.set_target(position_index_target, F::from_canonical_usize(position_index));
     pw.set_target(amount_target, amount);
 
@@ -144,6 +189,33 @@ pub fn make_withdraw_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F =
     // Ok(())
 }
 
+/// Checks a withdrawal proven by the owner with `make_withdraw_proof`: the proof must verify
+/// against the withdraw circuit built here, and take the tree from `withdraw_tx.root_before` to
+/// `withdraw_tx.root_after`. Needs no private key, so `withdraw_tx` is the sequencer's witness.
+pub fn check_withdraw_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
+    config: &CircuitConfig,
+    withdraw_tx: &Transaction<F, D>,
+    proof: ProofWithPublicInputs<F, C, D>,
+) -> Result<ProofTuple<F, C, D>>
+    where
+    [(); C::Hasher::HASH_SIZE]:,
+{
+    let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+    withdraw_circuit::<F, C, D>(withdraw_tx.position_tree_depth, &mut builder);
+    let data = builder.build::<C>();
+
+    data.verify(proof.clone())?;
+    ensure!(
+        proof.public_inputs[0..4] == withdraw_tx.root_before.elements,
+        "withdrawal proven against another root"
+    );
+    ensure!(
+        proof.public_inputs[4..8] == withdraw_tx.root_after.elements,
+        "withdrawal proven with another amount or nonce"
+    );
+    Ok((proof, data.verifier_only, data.common))
+}
+
 
 
 
@@ -155,9 +227,10 @@ mod tests {
     use plonky2::{plonk::config::{GenericConfig, PoseidonGoldilocksConfig}, fri::{FriConfig, reduction_strategies::FriReductionStrategy}};
 
     // use crate::{config::KeccakGoldilocksConfig2};
-    use crate::{verifier::{generate_proof_base64, generate_verifier_config, generate_circom_verifier}, types::{verify_proof, Cbn128}, sequencer::generate_transactions};
+    use crate::{verifier::{generate_proof_base64, generate_verifier_config, generate_circom_verifier}, types::{verify_proof, Cbn128}, sequencer::{generate_transactions, root_from_merkle_proof}};
     use crate::recursive::recursive_proof;
-    use crate::types::{F, C, D};
+    use crate::deposit::make_deposit_proof;
+    use crate::types::{F, C, D, Position, TransactionKind, withdraw_signature};
     use super::*;
 
 
@@ -171,12 +244,104 @@ mod tests {
     #[test]
     fn test_withdraw_proof() {
         let standard_config = CircuitConfig::standard_recursion_config();
-        let withdraw_tx = generate_transactions::<F, D>(vec![1]).unwrap();
+        let withdraw_tx = generate_transactions::<F, D>(vec![TransactionKind::Withdraw]).unwrap();
         let (pi, vd, cd) = 
             make_withdraw_proof::<F, C, D>(&standard_config, withdraw_tx[0].clone()).unwrap();
         verify_proof(pi, vd, cd).unwrap();
     }
 
+    #[test]
+    #[should_panic]
+    fn test_withdraw_more_than_balance() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let mut withdraw_tx = generate_transactions::<F, D>(vec![TransactionKind::Withdraw]).unwrap()[0].clone();
+        // The wrapped-around balance is committed in root_after, so only the range check fails.
+        withdraw_tx.amount = withdraw_tx.balance_before + F::ONE;
+        let position = Position {
+            pubkey: withdraw_tx.pubkey,
+            balance: withdraw_tx.balance_before - withdraw_tx.amount,
+            nonce: withdraw_tx.nonce + F::ONE,
+        };
+        withdraw_tx.root_after = root_from_merkle_proof(
+            position.to_leaf(),
+            withdraw_tx.position_index,
+            &withdraw_tx.merkle_proof,
+        );
+        make_withdraw_proof::<F, C, D>(&standard_config, withdraw_tx).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_withdraw_wraps_around_from_empty_balance() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let mut withdraw_tx = generate_transactions::<F, D>(vec![TransactionKind::Withdraw]).unwrap()[0].clone();
+        // The largest amount passing its own range check, from an empty position.
+        withdraw_tx.balance_before = F::ZERO;
+        withdraw_tx.amount = F::from_canonical_u64((1 << BALANCE_BITS) - 1);
+        let position = Position {
+            pubkey: withdraw_tx.pubkey,
+            balance: withdraw_tx.balance_before,
+            nonce: withdraw_tx.nonce,
+        };
+        withdraw_tx.root_before = root_from_merkle_proof(
+            position.to_leaf(),
+            withdraw_tx.position_index,
+            &withdraw_tx.merkle_proof,
+        );
+        let position = Position {
+            balance: withdraw_tx.balance_before - withdraw_tx.amount,
+            nonce: withdraw_tx.nonce + F::ONE,
+            ..position
+        };
+        withdraw_tx.root_after = root_from_merkle_proof(
+            position.to_leaf(),
+            withdraw_tx.position_index,
+            &withdraw_tx.merkle_proof,
+        );
+        make_withdraw_proof::<F, C, D>(&standard_config, withdraw_tx).unwrap();
+    }
+
+    #[test]
+    fn test_withdraw_signature() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let withdraw_tx = generate_transactions::<F, D>(vec![TransactionKind::Withdraw]).unwrap()[0].clone();
+        let signature = withdraw_signature(
+            withdraw_tx.private_key.unwrap(),
+            withdraw_tx.position_index,
+            withdraw_tx.amount,
+            withdraw_tx.nonce,
+        );
+        let (pi, _, _) =
+            make_withdraw_proof::<F, C, D>(&standard_config, withdraw_tx).unwrap();
+        assert_eq!(pi.public_inputs[8..12], signature.elements);
+    }
 
-}
+    #[test]
+    fn test_check_withdraw_proof() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let txs = generate_transactions::<F, D>(vec![TransactionKind::Deposit, TransactionKind::Withdraw]).unwrap();
+        let (deposit_pi, _, _) =
+            make_deposit_proof::<F, C, D>(&standard_config, txs[0].clone()).unwrap();
+        let withdraw_tx = txs[1].clone();
+        let (pi, _, _) =
+            make_withdraw_proof::<F, C, D>(&standard_config, withdraw_tx.clone()).unwrap();
+
+        // The sequencer's witness carries no key.
+        let unsigned_tx = Transaction { private_key: None, ..withdraw_tx };
+        check_withdraw_proof::<F, C, D>(&standard_config, &unsigned_tx, pi.clone()).unwrap();
+        assert!(check_withdraw_proof::<F, C, D>(&standard_config, &unsigned_tx, deposit_pi).is_err());
+        let other_tx = Transaction { root_after: unsigned_tx.root_before, ..unsigned_tx.clone() };
+        assert!(check_withdraw_proof::<F, C, D>(&standard_config, &other_tx, pi).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_withdraw_with_wrong_private_key() {
+        let standard_config = CircuitConfig::standard_recursion_config();
+        let mut withdraw_tx = generate_transactions::<F, D>(vec![TransactionKind::Withdraw]).unwrap()[0].clone();
+        withdraw_tx.private_key = Some(F::rand_arr());
+        make_withdraw_proof::<F, C, D>(&standard_config, withdraw_tx).unwrap();
+    }
 
+
+}
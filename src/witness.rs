@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, ensure, Result};
+use log::info;
+use num::{BigUint, Zero};
+use serde_json::Value as Json;
+use wasmer::{
+    imports, Function, Instance, LazyInit, Memory, MemoryType, Module, NativeFunc, Store, Value,
+    WasmerEnv,
+};
+
+/// Signal names of a circuit, read from the `.sym` file written by `circom --sym`.
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+    wires: HashMap<String, usize>,
+    names: HashMap<usize, String>,
+}
+
+impl SymbolTable {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Each line is `label,wire,component,name`. Signals removed by the optimizer have wire -1,
+    /// and several labels may share one wire, in which case the first name is kept.
+    pub fn parse(sym: &str) -> Result<Self> {
+        let mut table = Self::default();
+        for line in sym.lines().filter(|l| !l.is_empty()) {
+            let fields: Vec<&str> = line.splitn(4, ',').collect();
+            ensure!(fields.len() == 4, "malformed .sym line `{}`", line);
+            let wire: i64 = fields[1].parse()?;
+            if wire < 0 {
+                continue;
+            }
+            let name = fields[3].to_string();
+            table.names.entry(wire as usize).or_insert_with(|| name.clone());
+            table.wires.insert(name, wire as usize);
+        }
+        Ok(table)
+    }
+
+    pub fn wire(&self, name: &str) -> Option<usize> {
+        self.wires.get(name).copied()
+    }
+
+    pub fn name(&self, wire: usize) -> Option<&str> {
+        self.names.get(&wire).map(String::as_str)
+    }
+}
+
+/// Flattens the inputs of `proof.json` into each signal and its values in row-major order,
+/// together with the `.sym` name of every element, e.g. `main.wires_cap[0][3]`.
+pub fn flatten_inputs(input: &str) -> Result<Vec<(String, Vec<(String, BigUint)>)>> {
+    fn flatten(name: String, value: &Json, out: &mut Vec<(String, BigUint)>) -> Result<()> {
+        match value {
+            Json::Array(values) => {
+                for (i, v) in values.iter().enumerate() {
+                    flatten(format!("{}[{}]", name, i), v, out)?;
+                }
+            }
+            Json::String(s) => {
+                let v = BigUint::parse_bytes(s.as_bytes(), 10)
+                    .ok_or_else(|| anyhow!("`{}` is not a decimal number", s))?;
+                out.push((name, v));
+            }
+            Json::Number(n) => {
+                let v = n.as_u64().ok_or_else(|| anyhow!("`{}` is not a u64", n))?;
+                out.push((name, BigUint::from(v)));
+            }
+            _ => return Err(anyhow!("unexpected input value for `{}`", name)),
+        }
+        Ok(())
+    }
+
+    let input: serde_json::Map<String, Json> = serde_json::from_str(input)?;
+    input
+        .iter()
+        .map(|(signal, value)| {
+            let mut values = vec![];
+            flatten(format!("main.{}", signal), value, &mut values)?;
+            Ok((signal.clone(), values))
+        })
+        .collect()
+}
+
+// The wasm witness calculator addresses input signals by the 64-bit FNV-1a hash of their name.
+fn fnv1a(name: &str) -> (i32, i32) {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in name.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    ((hash >> 32) as i32, hash as i32)
+}
+
+// The errors raised by `exceptionHandler`, as named by the `witness_calculator.js` of circom.
+fn exception_message(code: i32) -> String {
+    match code {
+        1 => "signal not found".to_string(),
+        2 => "too many signals set".to_string(),
+        3 => "signal already set".to_string(),
+        4 => "assert failed".to_string(),
+        5 => "not enough memory".to_string(),
+        6 => "input signal array access exceeds the size".to_string(),
+        _ => format!("unknown error {}", code),
+    }
+}
+
+/// State of the `runtime` imports. The messages of the circuit are read back one character at a
+/// time through the `getMessageChar` export.
+#[derive(WasmerEnv, Clone, Default)]
+struct RuntimeEnv {
+    #[wasmer(export(name = "getMessageChar"))]
+    get_message_char: LazyInit<NativeFunc<(), i32>>,
+    #[wasmer(export(name = "getFieldNumLen32"))]
+    get_field_num_len32: LazyInit<NativeFunc<(), i32>>,
+    #[wasmer(export(name = "readSharedRWMemory"))]
+    read_shared_rw_memory: LazyInit<NativeFunc<i32, i32>>,
+    /// The messages printed before an exception, and the exception itself.
+    errors: Arc<Mutex<Vec<String>>>,
+    /// The line being written by `log`.
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl RuntimeEnv {
+    fn message(&self) -> String {
+        let mut message = String::new();
+        if let Some(get_message_char) = self.get_message_char_ref() {
+            while let Ok(c) = get_message_char.call() {
+                if c == 0 {
+                    break;
+                }
+                message.push(c as u8 as char);
+            }
+        }
+        message
+    }
+
+    fn shared_memory(&self) -> String {
+        match (self.get_field_num_len32_ref(), self.read_shared_rw_memory_ref()) {
+            (Some(len), Some(read)) => {
+                let limbs = (0..len.call().unwrap_or(0))
+                    .map(|i| read.call(i).unwrap_or(0) as u32)
+                    .collect::<Vec<_>>();
+                BigUint::from_slice(&limbs).to_string()
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn write_log(&self, part: String) {
+        let mut log = self.log.lock().unwrap();
+        if part == "\n" {
+            info!("{}", log.join(" "));
+            log.clear();
+        } else {
+            log.push(part);
+        }
+    }
+}
+
+/// Runs the witness calculator compiled by `circom --wasm`, in place of the C++ one.
+pub struct WitnessCalculator {
+    instance: Instance,
+    errors: Arc<Mutex<Vec<String>>>,
+    n32: usize,
+    pub prime: BigUint,
+}
+
+impl WitnessCalculator {
+    pub fn new<P: AsRef<Path>>(wasm: P) -> Result<Self> {
+        let store = Store::default();
+        let module = Module::from_file(&store, wasm)?;
+        let memory = Memory::new(&store, MemoryType::new(2000, None, false))?;
+        let env = RuntimeEnv::default();
+        // A failed assert prints where it failed, then raises an exception and traps.
+        let import_object = imports! {
+            "env" => {
+                "memory" => memory,
+            },
+            "runtime" => {
+                "exceptionHandler" => Function::new_native_with_env(
+                    &store,
+                    env.clone(),
+                    |env: &RuntimeEnv, code: i32| {
+                        env.errors.lock().unwrap().push(exception_message(code));
+                    },
+                ),
+                "printErrorMessage" => Function::new_native_with_env(
+                    &store,
+                    env.clone(),
+                    |env: &RuntimeEnv| {
+                        let message = env.message();
+                        env.errors.lock().unwrap().push(message);
+                    },
+                ),
+                "writeBufferMessage" => Function::new_native_with_env(
+                    &store,
+                    env.clone(),
+                    |env: &RuntimeEnv| env.write_log(env.message()),
+                ),
+                "showSharedRWMemory" => Function::new_native_with_env(
+                    &store,
+                    env.clone(),
+                    |env: &RuntimeEnv| env.write_log(env.shared_memory()),
+                ),
+            },
+        };
+        let instance = Instance::new(&module, &import_object)?;
+
+        let mut calculator = Self {
+            instance,
+            errors: env.errors,
+            n32: 0,
+            prime: BigUint::zero(),
+        };
+        calculator.n32 = calculator.call("getFieldNumLen32", &[])?.unwrap() as usize;
+        calculator.call("getRawPrime", &[])?;
+        calculator.prime = calculator.read_shared()?;
+        Ok(calculator)
+    }
+
+    /// Calls the export `name`, failing with the messages of the circuit if it raised an
+    /// exception.
+    fn call(&self, name: &str, args: &[Value]) -> Result<Option<i32>> {
+        let ret = self.instance.exports.get_function(name)?.call(args);
+        let errors = std::mem::take(&mut *self.errors.lock().unwrap());
+        if !errors.is_empty() {
+            bail!("`{}` failed: {}", name, errors.join("\n"));
+        }
+        Ok(ret?.first().map(|v| v.unwrap_i32()))
+    }
+
+    fn read_shared(&self) -> Result<BigUint> {
+        let mut limbs = Vec::with_capacity(self.n32);
+        for i in 0..self.n32 {
+            limbs.push(self.call("readSharedRWMemory", &[Value::I32(i as i32)])?.unwrap() as u32);
+        }
+        Ok(BigUint::from_slice(&limbs))
+    }
+
+    fn write_shared(&self, v: &BigUint) -> Result<()> {
+        let mut limbs = v.to_u32_digits();
+        ensure!(limbs.len() <= self.n32, "{} does not fit in the field", v);
+        limbs.resize(self.n32, 0);
+        for (i, limb) in limbs.into_iter().enumerate() {
+            self.call(
+                "writeSharedRWMemory",
+                &[Value::I32(i as i32), Value::I32(limb as i32)],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Computes the full witness for `input`, the contents of `proof.json`. Every input element
+    /// must be a signal of `main` in `sym`, and the witness is checked to hold it at that wire.
+    pub fn calculate(&self, sym: &SymbolTable, input: &str) -> Result<Vec<BigUint>> {
+        let inputs = flatten_inputs(input)?;
+        self.call("init", &[Value::I32(1)])?;
+
+        for (signal, values) in inputs.iter() {
+            let (msb, lsb) = fnv1a(signal);
+            // Older circom versions only check the sizes as the signals are set.
+            if self.instance.exports.get_function("getInputSignalSize").is_ok() {
+                let size = self
+                    .call("getInputSignalSize", &[Value::I32(msb), Value::I32(lsb)])?
+                    .unwrap();
+                ensure!(
+                    size >= 0 && size as usize == values.len(),
+                    "`{}` has {} elements but the circuit expects {}",
+                    signal,
+                    values.len(),
+                    size
+                );
+            }
+            for (i, (name, v)) in values.iter().enumerate() {
+                ensure!(sym.wire(name).is_some(), "`{}` is not a signal of the circuit", name);
+                ensure!(v < &self.prime, "`{}` is not a field element", name);
+                self.write_shared(v)?;
+                self.call(
+                    "setInputSignal",
+                    &[Value::I32(msb), Value::I32(lsb), Value::I32(i as i32)],
+                )?;
+            }
+        }
+
+        let size = self.call("getWitnessSize", &[])?.unwrap() as usize;
+        let mut witness = Vec::with_capacity(size);
+        for i in 0..size {
+            self.call("getWitness", &[Value::I32(i as i32)])?;
+            witness.push(self.read_shared()?);
+        }
+
+        for (name, v) in inputs.iter().flat_map(|(_, values)| values) {
+            let wire = sym.wire(name).unwrap();
+            ensure!(witness.get(wire) == Some(v), "the witness does not hold `{}`", name);
+        }
+        Ok(witness)
+    }
+}
+
+/// Writes `witness` in the snarkjs `.wtns` format, version 2.
+pub fn write_wtns<P: AsRef<Path>>(path: P, prime: &BigUint, witness: &[BigUint]) -> Result<()> {
+    let n8 = ((prime.bits() as usize + 63) / 64) * 8;
+    let to_bytes = |v: &BigUint| -> Result<Vec<u8>> {
+        let mut bytes = v.to_bytes_le();
+        ensure!(bytes.len() <= n8, "{} does not fit in {} bytes", v, n8);
+        bytes.resize(n8, 0);
+        Ok(bytes)
+    };
+
+    let mut out = Vec::with_capacity(44 + n8 * (witness.len() + 1));
+    out.write_all(b"wtns")?;
+    out.write_all(&2u32.to_le_bytes())?;
+    out.write_all(&2u32.to_le_bytes())?;
+
+    out.write_all(&1u32.to_le_bytes())?;
+    out.write_all(&(8 + n8 as u64).to_le_bytes())?;
+    out.write_all(&(n8 as u32).to_le_bytes())?;
+    out.write_all(&to_bytes(prime)?)?;
+    out.write_all(&(witness.len() as u32).to_le_bytes())?;
+
+    out.write_all(&2u32.to_le_bytes())?;
+    out.write_all(&((n8 * witness.len()) as u64).to_le_bytes())?;
+    for v in witness {
+        out.write_all(&to_bytes(v)?)?;
+    }
+
+    fs::write(path, out)?;
+    Ok(())
+}
+
+/// Reads a snarkjs `.wtns` file, returning the prime and the witness.
+pub fn read_wtns<P: AsRef<Path>>(path: P) -> Result<(BigUint, Vec<BigUint>)> {
+    let data = fs::read(path)?;
+    let sections = read_sections(&data, b"wtns")?;
+    let header = sections
+        .get(&1)
+        .ok_or_else(|| anyhow!("the .wtns file has no header"))?;
+    ensure!(header.len() >= 4, "truncated .wtns header");
+    let n8 = u32::from_le_bytes(header[0..4].try_into()?) as usize;
+    ensure!(header.len() == 8 + n8, "malformed .wtns header");
+    let prime = BigUint::from_bytes_le(&header[4..4 + n8]);
+    let len = u32::from_le_bytes(header[4 + n8..8 + n8].try_into()?) as usize;
+
+    let values = sections
+        .get(&2)
+        .ok_or_else(|| anyhow!("the .wtns file has no witness section"))?;
+    ensure!(values.len() == n8 * len, "the .wtns file does not hold {} values", len);
+    Ok((prime, values.chunks(n8).map(BigUint::from_bytes_le).collect()))
+}
+
+/// Splits an iden3 binary file into its sections, keyed by section type.
+fn read_sections<'a>(
+    data: &'a [u8],
+    magic: &[u8; 4],
+) -> Result<HashMap<u32, &'a [u8]>> {
+    ensure!(
+        data.len() >= 12 && &data[0..4] == magic,
+        "not a .{} file",
+        String::from_utf8_lossy(magic)
+    );
+    let num_sections = u32::from_le_bytes(data[8..12].try_into()?);
+    let mut sections = HashMap::new();
+    let mut pos = 12;
+    for _ in 0..num_sections {
+        ensure!(data.len() >= pos + 12, "truncated section header");
+        let ty = u32::from_le_bytes(data[pos..pos + 4].try_into()?);
+        let size = u64::from_le_bytes(data[pos + 4..pos + 12].try_into()?) as usize;
+        pos += 12;
+        ensure!(data.len() >= pos + size, "truncated section {}", ty);
+        sections.insert(ty, &data[pos..pos + size]);
+        pos += size;
+    }
+    Ok(sections)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::unique_temp_dir;
+
+    use super::*;
+
+    /// A circuit computing `c <== a * b`, compiled by circom 2.
+    const MULTIPLIER2: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/circom/test/fixtures/multiplier2/multiplier2");
+
+    #[test]
+    fn test_flatten_inputs() {
+        let input = r#"{"wires_cap": [["1", "2"], ["3", "4"]], "fri_pow_witness": "5"}"#;
+        let inputs = flatten_inputs(input).unwrap();
+        let wires_cap = &inputs.iter().find(|(s, _)| s == "wires_cap").unwrap().1;
+        assert_eq!(wires_cap.len(), 4);
+        assert_eq!(wires_cap[2], ("main.wires_cap[1][0]".to_string(), BigUint::from(3u32)));
+        let pow = &inputs.iter().find(|(s, _)| s == "fri_pow_witness").unwrap().1;
+        assert_eq!(pow[0], ("main.fri_pow_witness".to_string(), BigUint::from(5u32)));
+    }
+
+    #[test]
+    fn test_wtns_round_trip() {
+        let prime = BigUint::parse_bytes(
+            b"21888242871839275222246405745257275088548364400416034343698204186575808495617",
+            10,
+        )
+        .unwrap();
+        let witness = vec![BigUint::from(1u32), BigUint::from(u64::MAX), &prime - 1u32];
+        let path = unique_temp_dir().join("round_trip.wtns");
+        write_wtns(&path, &prime, &witness).unwrap();
+        assert_eq!(read_wtns(&path).unwrap(), (prime, witness));
+    }
+
+    #[test]
+    fn test_calculate() {
+        let calculator = WitnessCalculator::new(format!("{}.wasm", MULTIPLIER2)).unwrap();
+        let sym = SymbolTable::read(format!("{}.sym", MULTIPLIER2)).unwrap();
+
+        let witness = calculator.calculate(&sym, r#"{"a": "3", "b": "11"}"#).unwrap();
+        assert_eq!(witness, [1u32, 33, 3, 11].map(BigUint::from));
+        let path = unique_temp_dir().join("multiplier2.wtns");
+        write_wtns(&path, &calculator.prime, &witness).unwrap();
+        assert_eq!(read_wtns(&path).unwrap(), (calculator.prime.clone(), witness));
+
+        // The output `c` is not an input, so setting it raises an exception.
+        let err = calculator
+            .calculate(&sym, r#"{"a": "3", "b": "11", "c": "33"}"#)
+            .unwrap_err();
+        assert!(err.to_string().contains("too many signals set"), "{}", err);
+        assert!(calculator.calculate(&sym, r#"{"a": "3", "b": "11"}"#).is_ok());
+    }
+
+    #[test]
+    fn test_symbol_table() {
+        let sym = "1,1,0,main.public_inputs[0]\n2,-1,0,main.unused\n3,2,1,main.hasher.out\n4,2,0,main.alias\n";
+        let table = SymbolTable::parse(sym).unwrap();
+        assert_eq!(table.wire("main.public_inputs[0]"), Some(1));
+        assert_eq!(table.wire("main.unused"), None);
+        assert_eq!(table.wire("main.alias"), Some(2));
+        assert_eq!(table.name(2), Some("main.hasher.out"));
+    }
+}